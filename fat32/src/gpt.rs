@@ -0,0 +1,309 @@
+use std::{fmt, io};
+use std::mem::size_of;
+
+use mbr::{self, MasterBootRecord};
+use traits::BlockDevice;
+
+// Largest partition entry array we are willing to read, in bytes
+const MAX_ENTRIES_SIZE: u64 = 1 << 20;
+
+/// A globally unique identifier, as stored on disk: the first three fields
+/// are little endian, the last two are big endian.
+#[repr(C, packed)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// The type of unused partition entries.
+    pub const UNUSED: Guid = Guid([0; 16]);
+
+    /// The type of Microsoft Basic Data partitions,
+    /// EBD0A0A2-B9E5-4433-87C0-68B6B72699C7, used for FAT volumes.
+    pub const MICROSOFT_BASIC_DATA: Guid = Guid([
+        0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44,
+        0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7
+    ]);
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = self.0;
+        write!(f, "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-",
+               b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9])?;
+        for byte in &b[10..] {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Guid({})", self)
+    }
+}
+
+/// The header of a GUID partition table (GPT).
+#[repr(C, packed)]
+#[derive(Clone)]
+pub struct GptHeader {
+    signature: [u8; 8], // Should be "EFI PART"
+    pub revision: u32,
+    header_size: u32, // Size of the header covered by `header_crc32`
+    header_crc32: u32, // CRC32 of the header, computed with this field set to 0
+    _reserved: u32,
+    pub current_lba: u64, // The sector holding this copy of the header
+    pub backup_lba: u64, // The sector holding the other copy of the header
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: Guid,
+    pub partition_entries_lba: u64, // The first sector of the partition entry array
+    pub num_partition_entries: u32,
+    pub partition_entry_size: u32, // Should be 128 * 2^n
+    partition_entries_crc32: u32, // CRC32 of the partition entry array
+    _reserved_2: [u8; 420]
+}
+
+/// An entry of the partition entry array of a GUID partition table.
+#[repr(C, packed)]
+#[derive(Clone)]
+pub struct GptPartitionEntry {
+    pub type_guid: Guid, // `Guid::UNUSED` if the entry is not in use
+    pub unique_guid: Guid,
+    pub first_lba: u64,
+    pub last_lba: u64, // Inclusive
+    pub attributes: u64,
+    name: [u16; 36] // UTF-16, terminated by 0 if shorter
+}
+
+impl GptPartitionEntry {
+    /// Whether the entry describes a partition.
+    pub fn is_used(&self) -> bool {
+        self.type_guid != Guid::UNUSED
+    }
+
+    /// Whether the partition is a Microsoft Basic Data partition, which may
+    /// hold a FAT32 volume.
+    pub fn is_basic_data(&self) -> bool {
+        self.type_guid == Guid::MICROSOFT_BASIC_DATA
+    }
+
+    /// The number of sectors in the partition.
+    pub fn len(&self) -> u64 {
+        (self.last_lba + 1).saturating_sub(self.first_lba)
+    }
+
+    /// The name of the partition.
+    pub fn name(&self) -> String {
+        let name = self.name;
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        String::from_utf16_lossy(&name[..len])
+    }
+}
+
+impl fmt::Debug for GptPartitionEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GptPartitionEntry")
+            .field("type_guid", &{ self.type_guid })
+            .field("first_lba", &{ self.first_lba })
+            .field("last_lba", &{ self.last_lba })
+            .field("name", &self.name())
+            .finish()
+    }
+}
+
+/// A GUID partition table, read from a disk with a protective MBR.
+#[derive(Debug)]
+pub struct GuidPartitionTable {
+    pub header: GptHeader,
+    /// Every entry of the partition entry array, including unused ones.
+    pub partitions: Vec<GptPartitionEntry>,
+    is_backup: bool
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// There was an I/O error while reading the GPT.
+    Io(io::Error),
+    /// The protective MBR could not be read.
+    Mbr(mbr::Error),
+    /// The MBR has no protective (0xEE) partition, the disk is not
+    /// partitioned with a GPT.
+    NoProtectiveMbr,
+    /// The GPT header signature was invalid.
+    BadSignature,
+    /// The GPT header is inconsistent or its CRC32 doesn't match.
+    BadHeader,
+    /// The CRC32 of the partition entry array doesn't match the header.
+    BadPartitionEntries
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl GuidPartitionTable {
+    /// Reads and returns the GUID partition table (GPT) from `device`.
+    ///
+    /// The primary header in sector 1 is used if it and its partition entry
+    /// array are valid. Otherwise, the backup header is used instead: the one
+    /// the primary header points to, or the one in the last sector of
+    /// `device` if the primary header itself is damaged. When the size of
+    /// `device` is unknown, the last sector covered by the protective MBR
+    /// partition is tried instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoProtectiveMbr` if the MBR has no 0xEE partition. Returns
+    /// `BadSignature`, `BadHeader` or `BadPartitionEntries` if neither copy of
+    /// the GPT is valid, with the error found in the primary copy. Returns
+    /// `Io(err)` if the I/O error `err` occured while reading the GPT.
+    pub fn from<T: BlockDevice>(mut device: T) -> Result<GuidPartitionTable, Error> {
+        let mbr = MasterBootRecord::from(&mut device).map_err(|e| Error::Mbr(e))?;
+        let protective = mbr.find_partition_with_type(0xEE).ok_or(Error::NoProtectiveMbr)?;
+
+        let (primary_error, backup_lba) = match GuidPartitionTable::read_header(&mut device, 1) {
+            Ok(header) => {
+                let backup_lba = header.backup_lba;
+                match GuidPartitionTable::read_entries(&mut device, header) {
+                    Ok(gpt) => return Ok(gpt),
+                    Err(Error::Io(e)) => return Err(Error::Io(e)),
+                    Err(e) => (e, Some(backup_lba))
+                }
+            },
+            Err(Error::Io(e)) => return Err(Error::Io(e)),
+            Err(e) => {
+                // The protective partition only spans the whole disk
+                // if the disk is small enough for it
+                let backup_lba = match device.num_sectors() {
+                    Some(sectors) => sectors.checked_sub(1),
+                    None if protective.len != ::std::u32::MAX => {
+                        (protective.relative_sector as u64 + protective.len as u64).checked_sub(1)
+                    },
+                    None => None
+                };
+                (e, backup_lba)
+            }
+        };
+
+        let backup = match backup_lba {
+            Some(lba) if lba > 1 => GuidPartitionTable::read_header(&mut device, lba)
+                .and_then(|header| GuidPartitionTable::read_entries(&mut device, header)),
+            _ => return Err(primary_error)
+        };
+        match backup {
+            Ok(mut gpt) => {
+                gpt.is_backup = true;
+                Ok(gpt)
+            },
+            Err(Error::Io(e)) => Err(Error::Io(e)),
+            Err(_) => Err(primary_error)
+        }
+    }
+
+    // Read and validate a GPT header from sector `lba`
+    fn read_header<T: BlockDevice>(mut device: T, lba: u64) -> Result<GptHeader, Error> {
+        let mut buf = Vec::new();
+        device.read_all_sector(lba, &mut buf)?;
+        if buf.len() < size_of::<GptHeader>() {
+            return Err(Error::BadHeader);
+        }
+
+        let header = unsafe { (*(buf.as_ptr() as *const GptHeader)).clone() };
+        if &header.signature != b"EFI PART" {
+            return Err(Error::BadSignature);
+        }
+
+        // The CRC32 covers `header_size` bytes, with the CRC32 field zeroed
+        let header_size = header.header_size as usize;
+        if header_size < 92 || header_size > buf.len() {
+            return Err(Error::BadHeader);
+        }
+        for byte in buf[16..20].iter_mut() {
+            *byte = 0;
+        }
+        if crc32(&buf[..header_size]) != header.header_crc32 {
+            return Err(Error::BadHeader);
+        }
+
+        let entry_size = header.partition_entry_size as u64;
+        let entries_size = header.num_partition_entries as u64 * entry_size;
+        if header.current_lba != lba
+            || entry_size < size_of::<GptPartitionEntry>() as u64
+            || !entry_size.is_power_of_two()
+            || entries_size > MAX_ENTRIES_SIZE {
+            return Err(Error::BadHeader);
+        }
+        Ok(header)
+    }
+
+    // Read and validate the partition entry array `header` points to
+    fn read_entries<T: BlockDevice>(mut device: T, header: GptHeader) -> Result<GuidPartitionTable, Error> {
+        let entry_size = header.partition_entry_size as u64;
+        let entries_size = header.num_partition_entries as u64 * entry_size;
+
+        let mut entries = Vec::new();
+        let mut sector = header.partition_entries_lba;
+        while (entries.len() as u64) < entries_size {
+            if device.read_all_sector(sector, &mut entries)? == 0 {
+                return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                                    "Partition entry array is truncated")));
+            }
+            sector += 1;
+        }
+        entries.truncate(entries_size as usize);
+        if crc32(&entries) != header.partition_entries_crc32 {
+            return Err(Error::BadPartitionEntries);
+        }
+
+        let partitions = entries.chunks(entry_size as usize)
+            .map(|entry| unsafe { (*(entry.as_ptr() as *const GptPartitionEntry)).clone() })
+            .collect();
+
+        Ok(GuidPartitionTable {
+            header,
+            partitions,
+            is_backup: false
+        })
+    }
+
+    /// Whether the table was read from the backup header because the primary
+    /// one is damaged.
+    pub fn is_backup(&self) -> bool {
+        self.is_backup
+    }
+
+    /// Returns the Microsoft Basic Data partitions along with their index in
+    /// the partition entry array, in order.
+    pub fn basic_data_partitions(&self) -> Vec<(usize, &GptPartitionEntry)> {
+        self.partitions.iter()
+            .enumerate()
+            .filter(|&(_, partition)| partition.is_basic_data())
+            .collect()
+    }
+}
+
+impl fmt::Debug for GptHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GptHeader")
+            .field("current_lba", &{ self.current_lba })
+            .field("backup_lba", &{ self.backup_lba })
+            .field("disk_guid", &{ self.disk_guid })
+            .field("num_partition_entries", &{ self.num_partition_entries })
+            .finish()
+    }
+}
+
+/// Computes the CRC32 (IEEE 802.3, as used by GPT) of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
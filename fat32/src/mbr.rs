@@ -1,4 +1,6 @@
 use std::{fmt, io};
+use std::collections::HashSet;
+use std::mem::size_of;
 
 use traits::BlockDevice;
 use util::*;
@@ -6,28 +8,165 @@ use util::*;
 #[repr(C, packed)]
 #[derive(Copy, Clone, Debug)]
 pub struct CHS {
-    // CHS should be ignored by our implementation
-    _head: u8,
-    _sector_cylinder: u16
+    // CHS should be ignored when reading, see relative_sector
+    head: u8,
+    sector_cylinder: u16 // Bits 0-5: sector, bits 6-7 and 8-15: cylinder
+}
+
+impl CHS {
+    /// Heads per cylinder of the geometry used to compute CHS addresses.
+    pub const HEADS: u64 = 255;
+    /// Sectors per track of the geometry used to compute CHS addresses.
+    pub const SECTORS: u64 = 63;
+
+    /// Computes the CHS address of sector `lba` for a geometry of 255 heads
+    /// and 63 sectors per track. Sectors past cylinder 1023 can't be
+    /// addressed and are given the largest address, 1023/254/63.
+    pub fn from_lba(lba: u64) -> CHS {
+        let (cylinder, head, sector) = if lba >= 1024 * CHS::HEADS * CHS::SECTORS {
+            (1023, 254, 63)
+        } else {
+            (lba / (CHS::HEADS * CHS::SECTORS), (lba / CHS::SECTORS) % CHS::HEADS, lba % CHS::SECTORS + 1)
+        };
+
+        CHS {
+            head: head as u8,
+            sector_cylinder: (sector | ((cylinder >> 2) & 0xC0) | ((cylinder & 0xFF) << 8)) as u16
+        }
+    }
+
+    pub fn head(&self) -> u8 {
+        self.head
+    }
+
+    pub fn sector(&self) -> u8 {
+        (self.sector_cylinder & 0x3F) as u8
+    }
+
+    pub fn cylinder(&self) -> u16 {
+        let sector_cylinder = self.sector_cylinder;
+        ((sector_cylinder & 0xC0) << 2) | (sector_cylinder >> 8)
+    }
 }
 
 #[repr(C, packed)]
 #[derive(Debug, Clone)]
 pub struct PartitionEntry {
     pub bootable: u8, // 0x00: no, 0x80: yes
-    _starting_chs: CHS, // unused, see relative_sector
+    starting_chs: CHS, // unused when reading, see relative_sector
     pub partition_type: u8, // FAT32: 0xB or 0xC
-    _ending_chs: CHS, // unused, see relative_sector
+    ending_chs: CHS, // unused when reading, see relative_sector
     pub relative_sector: u32, // offset from the start of disk to the starting sector
     pub len: u32 // Total sectors in the partition
 }
 
+impl PartitionEntry {
+    /// Creates a non-bootable partition entry of type `partition_type`
+    /// spanning `len` sectors from sector `start`.
+    pub fn new(partition_type: u8, start: u32, len: u32) -> PartitionEntry {
+        let mut entry = PartitionEntry::empty();
+        entry.partition_type = partition_type;
+        entry.set_extent(start, len);
+        entry
+    }
+
+    /// Creates an unused partition entry.
+    pub fn empty() -> PartitionEntry {
+        PartitionEntry {
+            bootable: 0,
+            starting_chs: CHS { head: 0, sector_cylinder: 0 },
+            partition_type: 0,
+            ending_chs: CHS { head: 0, sector_cylinder: 0 },
+            relative_sector: 0,
+            len: 0
+        }
+    }
+
+    /// Whether the partition is marked active (bootable).
+    pub fn is_bootable(&self) -> bool {
+        self.bootable == 0x80
+    }
+
+    /// Marks the partition active (bootable) or not.
+    pub fn set_bootable(&mut self, bootable: bool) {
+        self.bootable = if bootable { 0x80 } else { 0x00 };
+    }
+
+    /// Sets the partition type, e.g. 0xC for FAT32 with LBA addressing.
+    pub fn set_partition_type(&mut self, partition_type: u8) {
+        self.partition_type = partition_type;
+    }
+
+    /// Makes the partition span `len` sectors from sector `start`, and
+    /// updates the CHS addresses of its first and last sectors to match.
+    pub fn set_extent(&mut self, start: u32, len: u32) {
+        self.relative_sector = start;
+        self.len = len;
+        self.starting_chs = CHS::from_lba(start as u64);
+        self.ending_chs = CHS::from_lba((start as u64 + len as u64).saturating_sub(1));
+    }
+
+    /// The CHS addresses of the first and last sectors of the partition.
+    pub fn chs(&self) -> (CHS, CHS) {
+        (self.starting_chs, self.ending_chs)
+    }
+
+    /// Whether the partition type is FAT32 with CHS (0xB) or LBA (0xC)
+    /// addressing.
+    pub fn is_fat32(&self) -> bool {
+        self.partition_type == 0xB || self.partition_type == 0xC
+    }
+
+    /// Whether the partition is an extended partition with CHS (0x5) or LBA
+    /// (0xF) addressing, holding logical partitions.
+    pub fn is_extended(&self) -> bool {
+        self.partition_type == 0x5 || self.partition_type == 0xF
+    }
+
+    // The FAT32 partition described by `self`, which is
+    // at index `index` of the partition table
+    fn as_fat_partition(&self, index: usize) -> Option<FatPartition> {
+        if self.is_fat32() {
+            Some(FatPartition {
+                index,
+                partition_type: self.partition_type,
+                start: self.relative_sector as u64,
+                len: self.len as u64
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// A FAT32 partition listed in a partition table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FatPartition {
+    /// The index of the partition in the partition table, as accepted by
+    /// `VFat::from_partition()`.
+    pub index: usize,
+    /// The partition type, 0xB or 0xC.
+    pub partition_type: u8,
+    /// The first sector of the partition.
+    pub start: u64,
+    /// The number of sectors in the partition.
+    pub len: u64
+}
+
+impl FatPartition {
+    /// The size of the partition in bytes for sectors of `sector_size` bytes.
+    pub fn size(&self, sector_size: u64) -> u64 {
+        self.len * sector_size
+    }
+}
+
 /// The master boot record (MBR).
 #[repr(C, packed)]
 #[derive(Clone)]
 pub struct MasterBootRecord {
-    _bootstrap: [u8; 436], // Bootstrap code, we don't need them here
-    _disk_id: [u8; 10], // Disk ID, we don't need them here
+    _bootstrap: [u8; 440], // Bootstrap code, we don't need them here
+    pub disk_signature: u32, // Disk signature, identifies the disk to the OS
+    _reserved: u16, // 0x5A5A if copy-protected, 0 otherwise
     pub partitions: [PartitionEntry; 4],
     signature: u16 // Should be 0xAA55
 }
@@ -40,9 +179,30 @@ pub enum Error {
     UnknownBootIndicator(u8),
     /// The MBR magic signature was invalid.
     BadSignature,
+    /// The chain of extended boot records links to sector `.0`, which is
+    /// outside of the extended partition or was already visited, or the EBR
+    /// in sector `.0` points past the sectors an MBR can address.
+    BadEbrLink(u64),
 }
 
 impl MasterBootRecord {
+    /// Creates an empty master boot record with disk signature
+    /// `disk_signature`, no bootstrap code and no partitions.
+    pub fn new(disk_signature: u32) -> MasterBootRecord {
+        MasterBootRecord {
+            _bootstrap: [0; 440],
+            disk_signature,
+            _reserved: 0,
+            partitions: [
+                PartitionEntry::empty(),
+                PartitionEntry::empty(),
+                PartitionEntry::empty(),
+                PartitionEntry::empty()
+            ],
+            signature: 0xAA55
+        }
+    }
+
     /// Reads and returns the master boot record (MBR) from `device`.
     ///
     /// # Errors
@@ -51,9 +211,14 @@ impl MasterBootRecord {
     /// Returns `UnknownBootIndicator(n)` if partition `n` contains an invalid
     /// boot indicator. Returns `Io(err)` if the I/O error `err` occured while
     /// reading the MBR.
-    pub fn from<T: BlockDevice>(mut device: T) -> Result<MasterBootRecord, Error> {
+    pub fn from<T: BlockDevice>(device: T) -> Result<MasterBootRecord, Error> {
+        MasterBootRecord::read_at(device, 0)
+    }
+
+    // Read and validate a MBR or EBR (same layout) from sector `sector`
+    fn read_at<T: BlockDevice>(mut device: T, sector: u64) -> Result<MasterBootRecord, Error> {
         let record: MasterBootRecord = unsafe {
-            device.read_sector_as::<MasterBootRecord>(0).map_err(|e| Error::Io(e))?
+            device.read_sector_as::<MasterBootRecord>(sector).map_err(|e| Error::Io(e))?
         };
 
         // Invalid signature
@@ -61,13 +226,48 @@ impl MasterBootRecord {
             return Err(Error::BadSignature);
         }
 
-        // Ensure every partition is valid
-        for (index, partition) in record.partitions.iter().enumerate() {
+        record.check_boot_indicators()?;
+        return Ok(record);
+    }
+
+    // Ensure every partition has a valid boot indicator
+    fn check_boot_indicators(&self) -> Result<(), Error> {
+        for (index, partition) in self.partitions.iter().enumerate() {
             if partition.bootable != 0x00 && partition.bootable != 0x80 {
                 return Err(Error::UnknownBootIndicator(index as u8));
             }
         }
-        return Ok(record);
+        Ok(())
+    }
+
+    /// Returns the 512 bytes of the master boot record as stored on disk,
+    /// ending with the 0xAA55 signature.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut record = self.clone();
+        record.signature = 0xAA55;
+        let bytes = unsafe {
+            ::std::slice::from_raw_parts(&record as *const MasterBootRecord as *const u8, size_of::<MasterBootRecord>())
+        };
+        bytes.to_vec()
+    }
+
+    /// Writes the master boot record to the first sector of `device`. The
+    /// rest of the sector, if larger than 512 bytes, is left untouched.
+    ///
+    /// # Errors
+    ///
+    /// Returns `UnknownBootIndicator(n)` without writing anything if the
+    /// `bootable` field of partition `n` is neither 0x00 nor 0x80. Returns
+    /// `Io(err)` if the I/O error `err` occured while writing the MBR.
+    pub fn write_to<T: BlockDevice>(&self, mut device: T) -> Result<(), Error> {
+        self.check_boot_indicators()?;
+
+        let mut sector = Vec::new();
+        device.read_all_sector(0, &mut sector).map_err(|e| Error::Io(e))?;
+        let bytes = self.to_bytes();
+        sector[..bytes.len()].copy_from_slice(&bytes);
+        device.write_sector(0, &sector).map_err(|e| Error::Io(e))?;
+        Ok(())
     }
 
     pub fn find_partition_with_type(&self, part_type: u8) -> Option<PartitionEntry> {
@@ -82,6 +282,81 @@ impl MasterBootRecord {
         }
         None
     }
+
+    /// Returns every FAT32 partition in the partition table, in order.
+    /// Logical partitions are not included, see `all_fat_partitions()`.
+    pub fn fat_partitions(&self) -> Vec<FatPartition> {
+        self.partitions.iter()
+            .enumerate()
+            .filter_map(|(index, partition)| partition.as_fat_partition(index))
+            .collect()
+    }
+
+    /// Returns every FAT32 partition in the partition table, followed by the
+    /// FAT32 logical partitions of the extended partition. Logical partitions
+    /// are numbered from 4 on, in the order of the EBR chain.
+    ///
+    /// # Errors
+    ///
+    /// See `logical_partitions()`.
+    pub fn all_fat_partitions<T: BlockDevice>(&self, device: T) -> Result<Vec<FatPartition>, Error> {
+        let mut partitions = self.fat_partitions();
+        for (i, partition) in self.logical_partitions(device)?.iter().enumerate() {
+            partitions.extend(partition.as_fat_partition(4 + i));
+        }
+        Ok(partitions)
+    }
+
+    /// Walks the chain of extended boot records (EBR) of the first extended
+    /// partition in the partition table, and returns the logical partitions
+    /// found in it in order. The `relative_sector` of the returned entries is
+    /// the absolute start sector of the logical partition.
+    ///
+    /// Returns an empty list if there is no extended partition.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadEbrLink(n)` if an EBR links to a sector `n` that is outside
+    /// of the extended partition or that was already visited, or if the EBR
+    /// in sector `n` describes a logical partition starting past the first
+    /// 2^32 sectors. Returns the same errors as `from()` if an EBR can't be
+    /// read or is invalid.
+    pub fn logical_partitions<T: BlockDevice>(&self, mut device: T) -> Result<Vec<PartitionEntry>, Error> {
+        let extended = match self.partitions.iter().find(|partition| partition.is_extended()) {
+            Some(extended) => extended.clone(),
+            None => return Ok(Vec::new())
+        };
+        let start = extended.relative_sector as u64;
+        let end = start + extended.len as u64;
+
+        let mut logical = Vec::new();
+        let mut visited = HashSet::new();
+        let mut sector = start;
+        loop {
+            if sector < start || sector >= end || !visited.insert(sector) {
+                return Err(Error::BadEbrLink(sector));
+            }
+            let ebr = MasterBootRecord::read_at(&mut device, sector)?;
+
+            // The first entry is the logical partition, relative to its EBR
+            let mut partition = ebr.partitions[0].clone();
+            if partition.partition_type != 0 {
+                partition.relative_sector = match sector.checked_add(partition.relative_sector as u64) {
+                    Some(absolute) if absolute <= ::std::u32::MAX as u64 => absolute as u32,
+                    _ => return Err(Error::BadEbrLink(sector))
+                };
+                logical.push(partition);
+            }
+
+            // The second entry links to the next EBR, relative to
+            // the start of the extended partition
+            let next = &ebr.partitions[1];
+            if next.partition_type == 0 || next.relative_sector == 0 {
+                return Ok(logical);
+            }
+            sector = start + next.relative_sector as u64;
+        }
+    }
 }
 
 impl fmt::Debug for MasterBootRecord {
@@ -57,6 +57,16 @@ macro vfat_from_resource($name:expr) {
     VFat::from(resource!($name)).expect("failed to initialize VFAT from image")
 }
 
+macro resource_bytes($name:expr) {{
+    let mut data = Vec::new();
+    resource!($name).read_to_end(&mut data).expect("read resource data");
+    data
+}}
+
+macro vfat_from_resource_mut($name:expr) {
+    VFat::from(Cursor::new(resource_bytes!($name))).expect("failed to initialize VFAT from image")
+}
+
 #[test]
 fn check_mbr_size() {
     check_size!(MasterBootRecord, 512);
@@ -349,3 +359,1092 @@ fn shared_fs_is_sync_send_static() {
     fn f<T: Sync + Send + 'static>() {  }
     f::<Shared<VFat>>();
 }
+
+/// A block device whose backing storage stays reachable after the device
+/// itself has been moved into a `CachedDevice`.
+#[derive(Clone)]
+struct SharedDevice(::std::sync::Arc<::std::sync::Mutex<Cursor<Vec<u8>>>>);
+
+impl SharedDevice {
+    fn new(size: usize) -> SharedDevice {
+        SharedDevice::with_data(vec![0u8; size])
+    }
+
+    fn with_data(data: Vec<u8>) -> SharedDevice {
+        SharedDevice(::std::sync::Arc::new(::std::sync::Mutex::new(Cursor::new(data))))
+    }
+
+    fn bytes(&self, start: usize, len: usize) -> Vec<u8> {
+        self.0.lock().unwrap().get_ref()[start..(start + len)].to_vec()
+    }
+}
+
+impl BlockDevice for SharedDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        self.0.lock().unwrap().read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> ::std::io::Result<usize> {
+        self.0.lock().unwrap().write_sector(n, buf)
+    }
+}
+
+#[test]
+fn test_cache_write_back() {
+    use vfat::{CachedDevice, Partition};
+
+    let device = SharedDevice::new(512 * 8);
+    let mut cache = CachedDevice::new(device.clone(), Partition { start: 2, sector_size: 1024 });
+
+    // Physical sector before the partition, then logical sector 3
+    // (physical sectors 4 and 5)
+    cache.write_sector(1, &[0xAB; 512]).expect("write physical sector");
+    cache.write_sector(3, &[0xCD; 1024]).expect("write logical sector");
+    cache.write_sector(2, &[0xEF; 16]).expect("partial write");
+
+    // Sectors before the partition are physical sized
+    assert_eq!(cache.write_sector(0, &[0x12; 1024]).expect("write physical sector"), 512);
+
+    let mut buf = [0u8; 1024];
+    assert_eq!(cache.read_sector(0, &mut buf).expect("read back"), 512);
+    assert_eq!(&buf[..512], &[0x12; 512][..]);
+    cache.read_sector(3, &mut buf).expect("read back");
+    assert_eq!(&buf[..], &[0xCD; 1024][..]);
+    assert_eq!(device.bytes(512, 512), vec![0; 512], "written before flush");
+
+    cache.flush().expect("flush");
+    assert_eq!(device.bytes(0, 512), vec![0x12; 512]);
+    assert_eq!(device.bytes(512, 512), vec![0xAB; 512]);
+    assert_eq!(device.bytes(1024, 16), vec![0xEF; 16]);
+    assert_eq!(device.bytes(1024 + 16, 1024 - 16), vec![0; 1024 - 16]);
+    assert_eq!(device.bytes(2048, 1024), vec![0xCD; 1024]);
+}
+
+#[test]
+fn test_cache_lru_eviction() {
+    use vfat::{CachedDevice, Partition};
+
+    let device = SharedDevice::new(512 * 8);
+    let mut cache = CachedDevice::with_capacity(device.clone(), Partition { start: 0, sector_size: 512 }, 2);
+    let mut buf = [0u8; 512];
+
+    cache.write_sector(1, &[0x11; 512]).expect("write sector 1");
+    cache.read_sector(2, &mut buf).expect("read sector 2");
+    cache.read_sector(1, &mut buf).expect("read sector 1");
+
+    // Sector 2 is the least recently used and clean
+    cache.read_sector(3, &mut buf).expect("read sector 3");
+    assert_eq!(device.bytes(512, 512), vec![0; 512], "dirty sector written early");
+
+    // Sector 1 is now the least recently used and must be written back
+    cache.read_sector(4, &mut buf).expect("read sector 4");
+    assert_eq!(device.bytes(512, 512), vec![0x11; 512], "evicted sector not written back");
+
+    cache.read_sector(1, &mut buf).expect("re-read sector 1");
+    assert_eq!(&buf[..], &[0x11; 512][..]);
+}
+
+#[test]
+fn test_cache_policies() {
+    use vfat::{CachePolicy, Lru, Clock, PinMetadata};
+
+    let mut lru = Lru::new();
+    for sector in 0..3 { lru.inserted(sector); }
+    lru.accessed(0);
+    assert_eq!(lru.victim(), Some(1));
+    lru.removed(1);
+    assert_eq!(lru.victim(), Some(2));
+    lru.accessed(2);
+    assert_eq!(lru.victim(), Some(0));
+
+    // Sector 0 was accessed again and gets a second chance
+    let mut clock = Clock::new();
+    for sector in 0..3 { clock.inserted(sector); }
+    assert_eq!(clock.victim(), Some(0));
+    clock.removed(0);
+    clock.inserted(3);
+    clock.accessed(1);
+    assert_eq!(clock.victim(), Some(2));
+    clock.removed(2);
+    clock.inserted(4);
+    assert_eq!(clock.victim(), Some(1));
+
+    // Sectors 10..20 are only evicted once nothing else is left
+    let mut pinned: PinMetadata<Lru> = PinMetadata::new(10..20);
+    pinned.inserted(10);
+    pinned.inserted(30);
+    pinned.accessed(10);
+    pinned.inserted(11);
+    assert_eq!(pinned.victim(), Some(30));
+    pinned.removed(30);
+    assert_eq!(pinned.victim(), Some(10));
+}
+
+#[test]
+fn test_cache_set_policy() {
+    use vfat::{CachedDevice, Partition, PinMetadata, Lru};
+
+    let device = SharedDevice::new(512 * 8);
+    let mut cache = CachedDevice::new(device.clone(), Partition { start: 0, sector_size: 512 });
+    let mut buf = [0u8; 512];
+
+    cache.write_sector(1, &[0x22; 512]).expect("write sector 1");
+    cache.read_sector(5, &mut buf).expect("read sector 5");
+    cache.read_sector(6, &mut buf).expect("read sector 6");
+
+    // Shrinking the cache evicts the unpinned sectors first
+    let policy: PinMetadata<Lru> = PinMetadata::new(0..2);
+    cache.set_policy(2, policy).expect("set policy");
+    assert_eq!(device.bytes(512, 512), vec![0; 512], "pinned sector evicted");
+
+    cache.read_sector(7, &mut buf).expect("read sector 7");
+    assert_eq!(device.bytes(512, 512), vec![0; 512], "pinned sector evicted");
+}
+
+#[test]
+fn test_cache_stats() {
+    use vfat::{CachedDevice, Partition, CacheStats};
+
+    let device = SharedDevice::new(512 * 8);
+    let mut cache = CachedDevice::with_capacity(device, Partition { start: 2, sector_size: 1024 }, 1);
+    let mut buf = [0u8; 1024];
+
+    cache.read_sector(2, &mut buf).expect("read sector 2");
+    cache.read_sector(2, &mut buf).expect("read sector 2");
+    cache.write_sector(2, &[1; 1024]).expect("write sector 2");
+    cache.write_sector(3, &[1; 1024]).expect("write sector 3");
+    cache.flush().expect("flush");
+
+    assert_eq!(cache.stats(), CacheStats {
+        hits: 2,
+        misses: 2,
+        evictions: 1,
+        write_backs: 2,
+        physical_reads: 2,
+        physical_writes: 4
+    });
+
+    cache.reset_stats();
+    assert_eq!(cache.stats(), CacheStats::default());
+}
+
+fn first_file_name(vfat: &Shared<VFat>) -> String {
+    vfat.open_dir("/")
+        .expect("root directory")
+        .entries()
+        .expect("entries iterator")
+        .find(|e| e.is_file() && e.as_file().unwrap().size() > 0)
+        .expect("a non-empty file in the root directory")
+        .name()
+        .to_string()
+}
+
+#[test]
+fn test_file_write() {
+    use std::io::SeekFrom;
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    let path = Path::new("/").join(first_file_name(&vfat));
+
+    let mut original = Vec::new();
+    vfat.open_file(&path).expect("file").read_to_end(&mut original).expect("read file");
+
+    // Overwrite the beginning of the file, then append enough data to need
+    // new clusters
+    let appended: Vec<u8> = (0..20000u32).map(|i| (i % 251) as u8).collect();
+    let mut file = vfat.open_file(&path).expect("file");
+    file.write_all(b"overwritten").expect("overwrite");
+    file.seek(SeekFrom::End(0)).expect("seek to end");
+    file.write_all(&appended).expect("append");
+    file.sync().expect("sync");
+
+    let mut expected = original.clone();
+    let len = ::std::cmp::min(expected.len(), 11);
+    expected[..len].copy_from_slice(&b"overwritten"[..len]);
+    if original.len() < 11 {
+        expected.extend_from_slice(&b"overwritten"[original.len()..]);
+    }
+    expected.extend_from_slice(&appended);
+
+    let mut file = vfat.open_file(&path).expect("file");
+    assert_eq!(file.size(), expected.len() as u64);
+    let mut actual = Vec::new();
+    file.read_to_end(&mut actual).expect("read file");
+    assert!(actual == expected, "file contents differ after write");
+}
+
+#[test]
+fn test_cluster_allocation() {
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    let mut vfat = vfat.borrow_mut();
+
+    // Mounting alone must not dirty FSInfo
+    vfat.flush().expect("flush");
+    assert_eq!(vfat.stats().physical_writes, 0);
+
+    let free = vfat.free_clusters().expect("free cluster count");
+    let first = vfat.alloc_clusters(None, 3).expect("allocate run");
+    assert_eq!(vfat.free_clusters().expect("free cluster count"), free - 3);
+
+    // A fresh image has plenty of room for a contiguous run
+    for i in 0..2 {
+        let cluster = ::vfat::Cluster::from(first.get() + i);
+        expect_variant!(vfat.fat_entry(cluster).expect("FAT entry").status(),
+            ::vfat::Status::Data(next) if next.get() == cluster.get() + 1);
+    }
+    let last = ::vfat::Cluster::from(first.get() + 2);
+    expect_variant!(vfat.fat_entry(last).expect("FAT entry").status(), ::vfat::Status::Eoc(_));
+
+    let next = vfat.alloc_cluster(Some(last)).expect("extend chain");
+    expect_variant!(vfat.fat_entry(last).expect("FAT entry").status(),
+        ::vfat::Status::Data(c) if c == next);
+    assert_eq!(vfat.free_clusters().expect("free cluster count"), free - 4);
+}
+
+#[test]
+fn test_fat_entry_mirroring() {
+    use vfat::{FatEntry, Status, Cluster};
+
+    let mut entry = FatEntry(0xF0000000);
+    entry.set_status(Status::Data(Cluster::from(5)));
+    assert_eq!({ entry.0 }, 0xF0000005);
+    entry.set_status(Status::Free);
+    assert_eq!({ entry.0 }, 0xF0000000);
+
+    let device = SharedDevice::with_data(resource_bytes!("mock1.fat32.img"));
+
+    let mbr = MasterBootRecord::from(device.clone()).expect("valid MBR");
+    let partition = mbr.find_partition_with_type(0xB)
+        .or_else(|| mbr.find_partition_with_type(0xC))
+        .expect("FAT32 partition");
+    let start = partition.relative_sector as u64;
+    let ebpb = BiosParameterBlock::from(device.clone(), start).expect("valid EBPB");
+
+    let vfat = VFat::from(device.clone()).expect("failed to initialize VFAT from image");
+    let cluster = vfat.borrow_mut().alloc_cluster(None).expect("allocate cluster");
+    vfat.borrow_mut().set_fat_entry(cluster, Status::Bad).expect("set FAT entry");
+    vfat.borrow_mut().flush().expect("flush");
+
+    let bytes_per_sector = ebpb.bytes_per_sector as u64;
+    let fat_start = start * 512 + ebpb.reserved_sectors as u64 * bytes_per_sector;
+    let fat_bytes = ebpb.get_sector_per_fat() as u64 * bytes_per_sector;
+    for fat in 0..(ebpb.fat_num as u64) {
+        let offset = fat_start + fat * fat_bytes + 4 * cluster.get() as u64;
+        let raw = device.bytes(offset as usize, 4);
+        assert_eq!(raw[..3], [0xF7, 0xFF, 0xFF], "FAT {} not updated", fat);
+        assert_eq!(raw[3] & 0x0F, 0x0F, "FAT {} not updated", fat);
+    }
+}
+
+#[test]
+fn test_create_file() {
+    use std::io::ErrorKind;
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+
+    let mut file = vfat.create_file("/A freshly created file.txt").expect("create file");
+    assert_eq!(file.size(), 0);
+    file.write_all(b"hello, world!").expect("write");
+    file.sync().expect("sync");
+
+    let mut contents = String::new();
+    vfat.open_file("/a freshly CREATED file.txt")
+        .expect("open created file")
+        .read_to_string(&mut contents)
+        .expect("read");
+    assert_eq!(contents, "hello, world!");
+
+    let names: Vec<_> = vfat.open_dir("/").expect("root directory")
+        .entries().expect("entries iterator")
+        .map(|e| e.name().to_string())
+        .collect();
+    assert!(names.iter().any(|n| n == "A freshly created file.txt"));
+
+    let e = vfat.create_file("/A FRESHLY CREATED FILE.TXT").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::AlreadyExists);
+    let e = vfat.create_file("/does/not/exist.txt").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    let e = vfat.create_file("relative.txt").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    let e = vfat.create_file("/bad:name.txt").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_create_dir() {
+    use std::io::ErrorKind;
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+
+    let dir = vfat.create_dir("/new directory", false).expect("create directory");
+    let names: Vec<_> = dir.entries().expect("entries iterator")
+        .map(|e| e.name().to_string())
+        .collect();
+    assert_eq!(names, vec![".", ".."]);
+
+    vfat.create_dir("/new directory/a/b/c", true).expect("create directories");
+    vfat.open_dir("/new directory/a/b/c").expect("nested directory");
+    let mut file = vfat.create_file("/new directory/a/b/c/file").expect("create file");
+    file.write_all(b"nested").expect("write");
+    file.sync().expect("sync");
+
+    let e = vfat.create_dir("/new directory/a/b", true).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::AlreadyExists);
+    let e = vfat.create_dir("/missing/dir", false).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    let e = vfat.create_dir("/new directory/a/b/c/file/d", true).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+
+    // The cluster of a directory that can't be inserted is given back
+    let full = vfat.create_dir("/full", false).expect("create directory");
+    let slots = vfat.borrow().bytes_per_cluster() / 32 - 2;
+    for i in 0..slots {
+        full.create_file(format!("F{}", i)).expect("create file");
+    }
+    let free = vfat.borrow_mut().free_clusters().expect("free clusters");
+    vfat.borrow_mut().alloc_clusters(None, free as usize - 1).expect("fill the disk");
+    full.create_dir("D").expect_err("no room to grow the directory");
+    assert_eq!(vfat.borrow_mut().free_clusters().expect("free clusters"), 1);
+}
+
+#[test]
+fn test_remove() {
+    use std::io::ErrorKind;
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    let free = vfat.borrow_mut().free_clusters().expect("free clusters");
+
+    vfat.create_dir("/to remove/nested", true).expect("create directories");
+    let mut file = vfat.create_file("/to remove/nested/file").expect("create file");
+    file.write_all(&[0xAB; 4096]).expect("write");
+    file.sync().expect("sync");
+
+    let e = vfat.remove("/to remove", false).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::Other);
+    let e = vfat.remove("/", true).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+
+    vfat.remove("/to remove", true).expect("remove recursively");
+    assert_eq!(vfat.open("/to remove").unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(vfat.borrow_mut().free_clusters().expect("free clusters"), free);
+
+    let e = vfat.remove("/to remove", true).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+    let e = vfat.remove("/NOPE/x", false).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn test_rename() {
+    use std::io::ErrorKind;
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+
+    vfat.create_dir("/from/sub", true).expect("create directories");
+    vfat.create_dir("/to", false).expect("create directory");
+    let mut file = vfat.create_file("/from/sub/file").expect("create file");
+    file.write_all(b"moved around").expect("write");
+    file.sync().expect("sync");
+
+    vfat.rename("/from/sub/file", "/from/sub/a much longer name than before").expect("rename");
+    vfat.rename("/from/sub/a much longer name than before", "/from/sub/FILE").expect("rename");
+    vfat.rename("/from/sub", "/to/sub").expect("move directory");
+
+    let mut data = String::new();
+    vfat.open_file("/to/sub/FILE").expect("moved file")
+        .read_to_string(&mut data).expect("read");
+    assert_eq!(data, "moved around");
+    assert_eq!(vfat.open("/from/sub").unwrap_err().kind(), ErrorKind::NotFound);
+
+    // `..` of the moved directory now refers to its new parent
+    let parent = vfat.open_dir("/to/sub").expect("moved directory")
+        .find("..").expect("..")
+        .into_dir().expect("directory");
+    assert!(parent.entries().expect("entries iterator").any(|e| e.name() == "sub"));
+
+    let e = vfat.rename("/to", "/to/sub/to").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    let e = vfat.rename("/to/sub", "/from").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::AlreadyExists);
+    let e = vfat.rename("/missing", "/other").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+    let e = vfat.rename("/NOPE/a", "/b").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+    let e = vfat.rename("/to", "/NOPE/b").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+
+    // A case-only rename keeps the 8.3 name and needs no LFN
+    vfat.create_file("/FOO.TXT").expect("create file");
+    vfat.rename("/FOO.TXT", "/foo.txt").expect("rename");
+    let entry = vfat.open("/foo.txt").expect("renamed file");
+    assert_eq!(entry.name(), "foo.txt");
+    assert_eq!(entry.short_name(), "FOO.TXT");
+    assert_eq!(entry.long_name(), None);
+}
+
+#[test]
+fn test_short_names() {
+    use vfat::name::{short_name, lfn_checksum};
+    use vfat::CodePage;
+
+    const CP437: CodePage = CodePage::CP437;
+
+    assert_eq!(&short_name("readme.txt", &[], &CP437), b"README  TXT");
+    assert_eq!(&short_name("Makefile", &[], &CP437), b"MAKEFILE   ");
+    assert_eq!(&short_name("A long file name.text", &[], &CP437), b"ALONGF~1TEX");
+    assert_eq!(&short_name(".bashrc", &[], &CP437), b"BASHRC~1   ");
+    assert_eq!(&short_name("my+file[1].c", &[], &CP437), b"MY_FIL~1C  ");
+    assert_eq!(&short_name("archive.tar.gz", &[], &CP437), b"ARCHIV~1GZ ");
+
+    let existing = [*b"README  TXT", *b"ALONGF~1TEX", *b"ALONGF~2TEX"];
+    assert_eq!(&short_name("README.txt", &existing, &CP437), b"README~1TXT");
+    assert_eq!(&short_name("a long file name.text", &existing, &CP437), b"ALONGF~3TEX");
+
+    let mut many = Vec::new();
+    for _ in 0..9 {
+        let next = short_name("Program Files", &many, &CP437);
+        many.push(next);
+    }
+    assert_eq!(&many[8], b"PROGRA~9   ");
+    assert_eq!(&short_name("Program Files", &many, &CP437), b"PROGR~10   ");
+
+    assert_eq!(lfn_checksum(b"README  TXT"), 0x73);
+}
+
+#[test]
+fn test_lfn_validation() {
+    use vfat::VFatExt;
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    let root_names = |vfat: &Shared<VFat>| -> Vec<String> {
+        vfat.open_dir("/").expect("root directory")
+            .entries().expect("entries iterator")
+            .map(|e| e.name().to_string())
+            .collect()
+    };
+
+    // The checksum no longer matches once the short name changes
+    let file = vfat.create_file("/Checksum mismatch.txt").expect("create file");
+    let mut raw = file.entry.read(&vfat).expect("read entry");
+    raw.set_short_name(b"OTHER   TXT");
+    file.entry.write(&vfat, &raw).expect("write entry");
+    let names = root_names(&vfat);
+    assert!(!names.iter().any(|n| n == "Checksum mismatch.txt"));
+    assert!(names.iter().any(|n| n == "OTHER.TXT"));
+
+    // The first LFN entry on disk must be flagged as the last one
+    let file = vfat.create_file("/Missing last flag.txt").expect("create file");
+    vfat.write_cluster(file.entry.dir, file.entry.start, &[0x02]).expect("write LFN");
+    let names = root_names(&vfat);
+    assert!(!names.iter().any(|n| n == "Missing last flag.txt"));
+    assert!(names.iter().any(|n| n == "MISSIN~1.TXT"));
+
+    // Sequence numbers have to be contiguous
+    let file = vfat.create_file("/A name long enough to need three LFN entries.txt").expect("create file");
+    vfat.write_cluster(file.entry.dir, file.entry.start + 32, &[0x01]).expect("write LFN");
+    let names = root_names(&vfat);
+    assert!(!names.iter().any(|n| n == "A name long enough to need three LFN entries.txt"));
+    assert!(names.iter().any(|n| n == "ANAMEL~1.TXT"));
+}
+
+#[test]
+fn test_nt_case_flags() {
+    use vfat::name::case_flags;
+    use vfat::CodePage;
+
+    const CP437: CodePage = CodePage::CP437;
+
+    assert_eq!(case_flags("readme.txt", b"README  TXT", &CP437), Some(0x18));
+    assert_eq!(case_flags("README.txt", b"README  TXT", &CP437), Some(0x10));
+    assert_eq!(case_flags("makefile", b"MAKEFILE   ", &CP437), Some(0x08));
+    assert_eq!(case_flags("FILE.TXT", b"FILE    TXT", &CP437), Some(0x00));
+    assert_eq!(case_flags("ReadMe.txt", b"README  TXT", &CP437), None);
+    assert_eq!(case_flags("a long name.txt", b"ALONGN~1TXT", &CP437), None);
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    let file = vfat.create_file("/notes.md").expect("create file");
+    assert_eq!(file.entry.start, file.entry.offset, "no LFN entries expected");
+    assert_eq!(&file.entry.read(&vfat).expect("read entry").short_name(), b"NOTES   MD ");
+
+    let file = vfat.create_file("/Notes2.md").expect("create file");
+    assert!(file.entry.start < file.entry.offset, "LFN entries expected");
+
+    let names: Vec<_> = vfat.open_dir("/").expect("root directory")
+        .entries().expect("entries iterator")
+        .map(|e| e.name().to_string())
+        .collect();
+    assert!(names.iter().any(|n| n == "notes.md"));
+    assert!(names.iter().any(|n| n == "Notes2.md"));
+}
+
+#[test]
+fn test_code_pages() {
+    use vfat::name::short_name;
+    use vfat::CodePage;
+
+    let cp437 = CodePage::CP437;
+    let cp850 = CodePage::CP850;
+    assert_eq!(cp437.decode_char(0x90), 'É');
+    assert_eq!(cp850.decode_char(0x9D), 'Ø');
+    assert_eq!(cp437.encode_char('Ø'), None);
+    assert_eq!(cp437.decode(b"\x05BC     "), "σBC");
+    assert_eq!(cp437.decode(b"R\x90SUM\x90  "), "RÉSUMÉ");
+
+    assert_eq!(&short_name("résumé.txt", &[], &cp437), b"R\x90SUM\x90  TXT");
+    assert_eq!(&short_name("øre.txt", &[], &cp437), b"_RE~1   TXT");
+    assert_eq!(&short_name("øre.txt", &[], &cp850), b"\x9DRE     TXT");
+    assert_eq!(&short_name("Õ.txt", &[], &cp850), b"\x05       TXT");
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    vfat.borrow_mut().set_code_page(cp850);
+    let file = vfat.create_file("/ØRE.TXT").expect("create file");
+    assert_eq!(file.entry.start, file.entry.offset, "no LFN entries expected");
+    assert!(vfat.open_dir("/").expect("root directory")
+        .entries().expect("entries iterator")
+        .any(|e| e.name() == "ØRE.TXT"));
+
+    // The same bytes mean something else in another code page
+    vfat.borrow_mut().set_code_page(cp437);
+    assert!(vfat.open_dir("/").expect("root directory")
+        .entries().expect("entries iterator")
+        .any(|e| e.name() == "¥RE.TXT"));
+}
+
+#[test]
+fn test_find_case_folding() {
+    use vfat::name::eq_ignore_case;
+
+    assert!(eq_ignore_case("Ünïcode.TXT", "ünÏcode.txt"));
+    assert!(eq_ignore_case("ΣΊΣΥΦΟΣ", "σίσυφοσ"));
+    assert!(!eq_ignore_case("straße", "STRASSE"));
+
+    // Characters whose folding differs from their lower case version
+    assert!(eq_ignore_case("ΟΔΥΣΣΕΥΣ", "οδυσσευς"));
+    assert!(eq_ignore_case("ſoſ", "SOS"));
+    assert!(eq_ignore_case("ϐίος", "ΒΊΟΣ"));
+    assert!(eq_ignore_case("µ", "Μ"));
+    assert!(!eq_ignore_case("ς", "ζ"));
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    vfat.create_file("/Ünïcode.TXT").expect("create file");
+    vfat.create_dir("/Program Files", false).expect("create directory");
+    vfat.create_file("/Program Files/Read Me.txt").expect("create file");
+
+    let entry = vfat.open("/ünÏcode.txt").expect("case folded lookup");
+    assert_eq!(entry.name(), "Ünïcode.TXT");
+
+    vfat.create_file("/Σίσυφος").expect("create file");
+    let entry = vfat.open("/ΣΊΣΥΦΟΣ").expect("case folded lookup");
+    assert_eq!(entry.name(), "Σίσυφος");
+    let e = vfat.create_file("/σίσυφοσ").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::AlreadyExists);
+
+    let entry = vfat.open("/progra~1/README~1.TXT").expect("lookup by 8.3 alias");
+    assert_eq!(entry.name(), "Read Me.txt");
+    assert_eq!(entry.short_name(), "README~1.TXT");
+
+    let e = vfat.create_file("/PROGRA~1").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::AlreadyExists);
+}
+
+#[test]
+fn test_entry_name_accessors() {
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    vfat.create_file("/Another long name.txt").expect("create file");
+    vfat.create_dir("/short", false).expect("create directory");
+
+    let entry = vfat.open("/Another long name.txt").expect("open file");
+    assert_eq!(entry.short_name(), "ANOTHE~1.TXT");
+    assert_eq!(entry.long_name(), Some("Another long name.txt"));
+    assert_eq!(entry.attributes(), 0x20);
+
+    let entry = vfat.open("/short").expect("open directory");
+    assert_eq!(entry.name(), "short");
+    assert_eq!(entry.short_name(), "SHORT");
+    assert_eq!(entry.long_name(), None);
+    assert_eq!(entry.attributes(), 0x10);
+
+    let root = vfat.open_dir("/").expect("root directory");
+    assert_eq!(root.long_name(), None);
+    assert_eq!(root.attributes(), 0x10);
+}
+
+#[test]
+fn test_attributes() {
+    use vfat::Attributes;
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    let mut file = vfat.create_file("/attributes.txt").expect("create file");
+    file.set_attributes(Attributes::from(Attributes::HIDDEN | Attributes::SYSTEM)).expect("set attributes");
+
+    let entry = vfat.open("/attributes.txt").expect("open file");
+    assert!(entry.metadata().is_hidden());
+    assert!(entry.metadata().is_system());
+    assert!(!entry.metadata().is_archive());
+    assert!(!entry.metadata().is_read_only());
+
+    // Writing marks the file for archiving
+    file.write_all(b"modified").expect("write");
+    file.sync().expect("sync");
+    let mut entry = vfat.open("/attributes.txt").expect("open file");
+    assert_eq!(entry.attributes(), Attributes::HIDDEN | Attributes::SYSTEM | Attributes::ARCHIVE);
+
+    // Only the mutable attributes change
+    entry.set_attributes(Attributes::from(Attributes::READ_ONLY | Attributes::DIRECTORY)).expect("set attributes");
+    let entry = vfat.open("/attributes.txt").expect("open file");
+    assert_eq!(entry.attributes(), Attributes::READ_ONLY);
+    assert!(entry.metadata().read_only());
+
+    let mut dir = vfat.create_dir("/attributes", false).expect("create directory");
+    dir.set_attributes(Attributes::from(Attributes::HIDDEN)).expect("set attributes");
+    let entry = vfat.open("/attributes").expect("open directory");
+    assert!(entry.metadata().is_directory());
+    assert!(entry.metadata().hidden());
+
+    let mut root = vfat.open_dir("/").expect("root directory");
+    let e = root.set_attributes(Attributes::from(Attributes::HIDDEN)).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_timestamp_precision() {
+    use vfat::VFatExt;
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    let file = vfat.create_file("/timestamps").expect("create file");
+
+    let time: u16 = (13 << 11) | (45 << 5) | 29;
+    let date: u16 = ((2018 - 1980) << 9) | (3 << 5) | 14;
+    let mut raw = [0u8; 7];
+    raw[0] = 150;
+    raw[1..3].copy_from_slice(&[time as u8, (time >> 8) as u8]);
+    raw[3..5].copy_from_slice(&[date as u8, (date >> 8) as u8]);
+    raw[5..7].copy_from_slice(&[date as u8, (date >> 8) as u8]);
+    vfat.write_cluster(file.entry.dir, file.entry.offset + 13, &raw).expect("write entry");
+
+    let entry = vfat.open("/timestamps").expect("open file");
+    let created = entry.metadata().created();
+    assert!(!created.is_date_only());
+    assert_eq!((created.year(), created.month(), created.day()), (2018, 3, 14));
+    assert_eq!((created.hour(), created.minute(), created.second()), (13, 45, 59));
+    assert_eq!(created.millisecond(), 500);
+
+    let accessed = entry.metadata().accessed();
+    assert!(accessed.is_date_only());
+    assert_eq!((accessed.year(), accessed.month(), accessed.day()), (2018, 3, 14));
+    assert_eq!((accessed.hour(), accessed.minute(), accessed.second()), (0, 0, 0));
+    assert_eq!(accessed.millisecond(), 0);
+
+    // Centiseconds past 199 are ignored rather than carried into the minute
+    raw[0] = 200;
+    vfat.write_cluster(file.entry.dir, file.entry.offset + 13, &raw).expect("write entry");
+    let created = vfat.open("/timestamps").expect("open file").metadata().created();
+    assert_eq!((created.minute(), created.second(), created.millisecond()), (45, 58, 0));
+}
+
+#[test]
+fn test_system_time_conversions() {
+    use std::time::{SystemTime, Duration, UNIX_EPOCH};
+    use vfat::{Timestamp, Date, Time};
+
+    // 2018-03-14 13:45:59.500 UTC
+    let time = UNIX_EPOCH + Duration::new(1521035159, 500_000_000);
+    let ts = Timestamp::from(time);
+    assert_eq!(ts.date, Date::new(2018, 3, 14));
+    assert_eq!(ts.time, Some(Time::new(13, 45, 58)));
+    assert_eq!((ts.second(), ts.millisecond()), (59, 500));
+    assert_eq!(SystemTime::from(ts), time);
+
+    // Local time one hour east of UTC
+    let ts = Timestamp::from_system_time(time, 3600);
+    assert_eq!((ts.hour(), ts.minute()), (14, 45));
+    assert_eq!(ts.to_system_time(3600), time);
+
+    // Without centiseconds, times are rounded down to 2 seconds
+    let ts = Timestamp::new(Date::new(2018, 3, 14), Time::new(13, 45, 59));
+    assert_eq!(SystemTime::from(ts), UNIX_EPOCH + Duration::from_secs(1521035158));
+
+    // Times outside of the FAT range are clamped
+    let ts = Timestamp::from(UNIX_EPOCH);
+    assert_eq!((ts.year(), ts.month(), ts.day(), ts.hour(), ts.second()), (1980, 1, 1, 0, 0));
+    let ts = Timestamp::from(UNIX_EPOCH + Duration::from_secs(1 << 40));
+    assert_eq!((ts.year(), ts.month(), ts.day()), (2107, 12, 31));
+    assert_eq!((ts.hour(), ts.minute(), ts.second(), ts.millisecond()), (23, 59, 59, 0));
+
+    // New entries are stamped with the current time
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    let before = SystemTime::now() - Duration::from_secs(2);
+    vfat.create_file("/stamped").expect("create file");
+    let entry = vfat.open("/stamped").expect("open file");
+    let created = SystemTime::from(entry.metadata().created());
+    assert!(created >= before && created <= SystemTime::now());
+    assert!(SystemTime::from(entry.metadata().modified()) >= before - Duration::from_secs(2));
+}
+
+#[test]
+fn test_mount_without_mbr() {
+    let data = resource_bytes!("mock1.fat32.img");
+    let mbr = MasterBootRecord::from(Cursor::new(data.clone())).expect("valid MBR");
+    let partition = mbr.find_partition_with_type(0xB)
+        .or_else(|| mbr.find_partition_with_type(0xC))
+        .expect("FAT32 partition");
+    let start = partition.relative_sector as usize;
+    let root_names = |vfat: Shared<VFat>| -> Vec<String> {
+        vfat.open_dir("/").expect("root directory")
+            .entries().expect("entries iterator")
+            .map(|e| e.name().to_string())
+            .collect()
+    };
+    let expected = root_names(VFat::from(Cursor::new(data.clone())).expect("mount from MBR"));
+
+    // A partitioned image mounted at the start of the partition
+    let vfat = VFat::from_volume(Cursor::new(data.clone()), start as u64).expect("mount volume");
+    assert_eq!(root_names(vfat), expected);
+
+    // The same volume without anything before it
+    let superfloppy = data[(start * 512)..].to_vec();
+    match VFat::from(Cursor::new(superfloppy.clone())) {
+        Err(::vfat::Error::Mbr(_)) | Err(::vfat::Error::NotFound) => {},
+        other => panic!("expected no MBR, got {:?}", other.map(|_| ()))
+    }
+    let vfat = VFat::from_volume(Cursor::new(superfloppy.clone()), 0).expect("mount superfloppy");
+    assert_eq!(root_names(vfat), expected);
+
+    // Auto-detection handles both layouts
+    let vfat = VFat::from_any(Cursor::new(superfloppy)).expect("detect superfloppy");
+    assert_eq!(root_names(vfat), expected);
+    let vfat = VFat::from_any(Cursor::new(data)).expect("detect MBR");
+    assert_eq!(root_names(vfat), expected);
+}
+
+// Copy of the image `data` with a second FAT32 partition at index `index` of
+// the partition table, holding a copy of the first one, appended to the image
+fn with_second_partition(data: &[u8], index: usize) -> Vec<u8> {
+    let mbr = MasterBootRecord::from(Cursor::new(data.to_vec())).expect("valid MBR");
+    let first = mbr.fat_partitions()[0];
+    let (start, len) = (first.start as usize, first.len as usize);
+
+    let mut disk = data.to_vec();
+    disk.resize((start + len) * 512, 0);
+    let copy = disk[(start * 512)..((start + len) * 512)].to_vec();
+    let second = disk.len() / 512;
+    disk.extend_from_slice(&copy);
+
+    let entry = 446 + index * 16;
+    disk[entry..(entry + 16)].copy_from_slice(&[0; 16]);
+    disk[entry + 4] = 0xC;
+    disk[(entry + 8)..(entry + 12)].copy_from_slice(&[second as u8, (second >> 8) as u8, (second >> 16) as u8, 0]);
+    disk[(entry + 12)..(entry + 16)].copy_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, 0]);
+    disk
+}
+
+#[test]
+fn test_mount_partition() {
+    let disk = with_second_partition(&resource_bytes!("mock1.fat32.img"), 2);
+
+    let mbr = MasterBootRecord::from(Cursor::new(disk.clone())).expect("valid MBR");
+    let partitions = mbr.fat_partitions();
+    assert_eq!(partitions.len(), 2);
+    assert_eq!(partitions[1].index, 2);
+    assert_eq!(partitions[1].len, partitions[0].len);
+    assert_eq!(partitions[1].size(512), partitions[0].len * 512);
+
+    let device = SharedDevice::with_data(disk);
+    {
+        let vfat = VFat::from_partition(device.clone(), 2).expect("mount second partition");
+        vfat.create_file("/only in the second partition").expect("create file");
+        vfat.borrow_mut().flush().expect("flush");
+    }
+
+    let vfat = VFat::from_partition(device.clone(), partitions[0].index).expect("mount first partition");
+    assert!(vfat.open("/only in the second partition").is_err());
+    let vfat = VFat::from_partition(device.clone(), 2).expect("mount second partition");
+    vfat.open("/only in the second partition").expect("file in the second partition");
+
+    match VFat::from_partition(device.clone(), 3) {
+        Err(::vfat::Error::NotFound) => {},
+        other => panic!("expected NotFound, got {:?}", other.map(|_| ()))
+    }
+}
+
+// Write a partition table entry of type `kind` at `lba` of `len` sectors into
+// slot `slot` of the MBR or EBR in `sector`
+fn write_partition_entry(disk: &mut [u8], sector: usize, slot: usize, kind: u8, lba: u32, len: u32) {
+    let entry = sector * 512 + 446 + slot * 16;
+    disk[entry..(entry + 16)].copy_from_slice(&[0; 16]);
+    disk[entry + 4] = kind;
+    for i in 0..4 {
+        disk[entry + 8 + i] = (lba >> (8 * i)) as u8;
+        disk[entry + 12 + i] = (len >> (8 * i)) as u8;
+    }
+    disk[(sector * 512 + 510)..(sector * 512 + 512)].copy_from_slice(&[0x55, 0xAA]);
+}
+
+// The first FAT32 volume of the image `data`
+fn first_volume(data: &[u8]) -> Vec<u8> {
+    let mbr = MasterBootRecord::from(Cursor::new(data.to_vec())).expect("valid MBR");
+    let first = mbr.fat_partitions()[0];
+    let (start, len) = (first.start as usize, first.len as usize);
+    data[(start * 512)..((start + len) * 512)].to_vec()
+}
+
+// A disk whose MBR has an extended partition at sector 64 holding two logical
+// partitions with a copy of `volume` each, each one preceded by its EBR
+fn with_logical_partitions(volume: &[u8]) -> Vec<u8> {
+    let (extended, len) = (64, volume.len() / 512);
+    let mut disk = vec![0u8; extended * 512];
+    for _ in 0..2 {
+        disk.extend_from_slice(&[0; 512]);
+        disk.extend_from_slice(volume);
+    }
+    write_partition_entry(&mut disk, 0, 3, 0xF, extended as u32, 2 * (1 + len as u32));
+    write_partition_entry(&mut disk, extended, 0, 0xC, 1, len as u32);
+    write_partition_entry(&mut disk, extended, 1, 0x5, 1 + len as u32, 1 + len as u32);
+    write_partition_entry(&mut disk, extended + 1 + len, 0, 0xC, 1, len as u32);
+    disk
+}
+
+#[test]
+fn test_logical_partitions() {
+    use mbr::Error;
+
+    let (extended, len) = (64, 16);
+    let disk = with_logical_partitions(&vec![0; len * 512]);
+
+    let mbr = MasterBootRecord::from(Cursor::new(disk.clone())).expect("valid MBR");
+    let logical = mbr.logical_partitions(Cursor::new(disk.clone())).expect("logical partitions");
+    assert_eq!(logical.len(), 2);
+    assert_eq!({ logical[0].relative_sector } as usize, extended + 1);
+    assert_eq!({ logical[1].relative_sector } as usize, extended + 2 + len);
+
+    let partitions = mbr.all_fat_partitions(Cursor::new(disk.clone())).expect("FAT32 partitions");
+    let indices: Vec<_> = partitions.iter().map(|p| p.index).collect();
+    assert_eq!(indices, [4, 5]);
+
+    // A link back to an EBR that was already visited
+    let mut cyclic = disk.clone();
+    write_partition_entry(&mut cyclic, extended + 1 + len, 1, 0x5, 1 + len as u32, 1);
+    match mbr.logical_partitions(Cursor::new(cyclic)) {
+        Err(Error::BadEbrLink(sector)) => assert_eq!(sector as usize, extended + 1 + len),
+        other => panic!("expected a bad EBR link, got {:?}", other)
+    }
+
+    // A link outside of the extended partition
+    let mut out_of_range = disk.clone();
+    write_partition_entry(&mut out_of_range, extended, 1, 0x5, 0x1000000, 1);
+    match mbr.logical_partitions(Cursor::new(out_of_range)) {
+        Err(Error::BadEbrLink(sector)) => assert_eq!(sector as usize, extended + 0x1000000),
+        other => panic!("expected a bad EBR link, got {:?}", other)
+    }
+
+    // A logical partition starting past what 32 bits can address
+    let mut too_far = disk.clone();
+    write_partition_entry(&mut too_far, extended, 0, 0xC, 0xFFFFFFFF, 1);
+    match mbr.logical_partitions(Cursor::new(too_far)) {
+        Err(Error::BadEbrLink(sector)) => assert_eq!(sector as usize, extended),
+        other => panic!("expected a bad EBR link, got {:?}", other)
+    }
+}
+
+#[test]
+fn test_mount_logical_partition() {
+    let disk = with_logical_partitions(&first_volume(&resource_bytes!("mock1.fat32.img")));
+
+    let device = SharedDevice::with_data(disk);
+    {
+        let vfat = VFat::from_partition(device.clone(), 5).expect("mount logical partition");
+        vfat.create_file("/in the second logical partition").expect("create file");
+        vfat.borrow_mut().flush().expect("flush");
+    }
+    let vfat = VFat::from_partition(device.clone(), 4).expect("mount logical partition");
+    assert!(vfat.open("/in the second logical partition").is_err());
+    let vfat = VFat::from_partition(device.clone(), 5).expect("mount logical partition");
+    vfat.open("/in the second logical partition").expect("file in the second logical partition");
+}
+
+// Write a GPT header at `lba` whose partition entry array is at `entries_lba`
+fn write_gpt_header(disk: &mut [u8], lba: usize, backup_lba: usize, entries_lba: usize) {
+    use gpt::crc32;
+
+    let entries_crc = crc32(&disk[(entries_lba * 512)..(entries_lba * 512 + 128 * 128)]);
+    let mut header = [0u8; 92];
+    header[..8].copy_from_slice(b"EFI PART");
+    header[10] = 1;
+    header[12] = 92;
+    for i in 0..8 {
+        header[24 + i] = (lba >> (8 * i)) as u8;
+        header[32 + i] = (backup_lba >> (8 * i)) as u8;
+        header[72 + i] = (entries_lba >> (8 * i)) as u8;
+    }
+    header[80] = 128;
+    header[84] = 128;
+    for i in 0..4 {
+        header[88 + i] = (entries_crc >> (8 * i)) as u8;
+    }
+    let header_crc = crc32(&header);
+    for i in 0..4 {
+        header[16 + i] = (header_crc >> (8 * i)) as u8;
+    }
+    disk[(lba * 512)..(lba * 512 + 92)].copy_from_slice(&header);
+}
+
+// A GPT disk with two Basic Data partitions: an empty one
+// followed by a copy of `volume`
+fn gpt_disk(volume: &[u8]) -> Vec<u8> {
+    use gpt::Guid;
+
+    let len = volume.len() / 512;
+    let total = 2048 + 8 + len + 33;
+    let mut disk = vec![0u8; total * 512];
+    disk[((2048 + 8) * 512)..((2048 + 8 + len) * 512)].copy_from_slice(volume);
+    write_partition_entry(&mut disk, 0, 0, 0xEE, 1, (total - 1) as u32);
+
+    let mut entries = vec![0u8; 128 * 128];
+    for (i, &(first_lba, last_lba, name)) in [(2048usize, 2055usize, "EMPTY"), (2056, 2055 + len, "DATA")].iter().enumerate() {
+        let entry = &mut entries[(i * 128)..((i + 1) * 128)];
+        entry[..16].copy_from_slice(&Guid::MICROSOFT_BASIC_DATA.0);
+        entry[16] = i as u8 + 1;
+        for j in 0..8 {
+            entry[32 + j] = (first_lba >> (8 * j)) as u8;
+            entry[40 + j] = (last_lba >> (8 * j)) as u8;
+        }
+        for (j, c) in name.encode_utf16().enumerate() {
+            entry[56 + 2 * j] = c as u8;
+        }
+    }
+    disk[(2 * 512)..(34 * 512)].copy_from_slice(&entries);
+    disk[((total - 33) * 512)..((total - 1) * 512)].copy_from_slice(&entries);
+    write_gpt_header(&mut disk, 1, total - 1, 2);
+    write_gpt_header(&mut disk, total - 1, 1, total - 33);
+    disk
+}
+
+#[test]
+fn test_gpt() {
+    use gpt::{self, crc32, Guid, GuidPartitionTable};
+
+    assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    assert_eq!(Guid::MICROSOFT_BASIC_DATA.to_string(), "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7");
+
+    let disk = gpt_disk(&vec![0; 16 * 512]);
+    let total = disk.len() / 512;
+
+    let gpt = GuidPartitionTable::from(Cursor::new(disk.clone())).expect("valid GPT");
+    assert!(!gpt.is_backup());
+    assert_eq!(gpt.partitions.len(), 128);
+    let partitions = gpt.basic_data_partitions();
+    assert_eq!(partitions.len(), 2);
+    assert_eq!((partitions[1].0, partitions[1].1.name()), (1, "DATA".to_string()));
+    assert_eq!({ partitions[1].1.first_lba }, 2056);
+    assert!(!gpt.partitions[2].is_used());
+
+    // A damaged primary header or entry array falls back to the backup,
+    // even if the protective partition doesn't tell where the disk ends
+    for &offset in [512 + 40, 2 * 512 + 128 + 32].iter() {
+        let mut damaged = disk.clone();
+        damaged[offset] ^= 0xFF;
+        write_partition_entry(&mut damaged, 0, 0, 0xEE, 1, 0xFFFFFFFF);
+        let gpt = GuidPartitionTable::from(Cursor::new(damaged.clone())).expect("backup GPT");
+        assert!(gpt.is_backup());
+        assert_eq!({ gpt.header.current_lba } as usize, total - 1);
+        assert_eq!({ gpt.basic_data_partitions()[1].1.first_lba }, 2056);
+    }
+
+    // The backup header the primary one points to need not be at the end
+    let mut damaged = disk.clone();
+    damaged[2 * 512 + 128 + 32] ^= 0xFF;
+    damaged.extend_from_slice(&[0; 8 * 512]);
+    let gpt = GuidPartitionTable::from(Cursor::new(damaged)).expect("backup GPT");
+    assert_eq!({ gpt.header.current_lba } as usize, total - 1);
+
+    // Without knowing the size of the device,
+    // the protective partition tells where the backup is
+    let mut damaged = disk.clone();
+    damaged[512 + 40] ^= 0xFF;
+    let gpt = GuidPartitionTable::from(SharedDevice::with_data(damaged)).expect("backup GPT");
+    assert!(gpt.is_backup());
+
+    let mut damaged = disk.clone();
+    damaged[2 * 512 + 128 + 32] ^= 0xFF;
+    damaged[(total - 1) * 512] ^= 0xFF;
+    expect_variant!(GuidPartitionTable::from(Cursor::new(damaged)), Err(gpt::Error::BadPartitionEntries));
+
+    let mut damaged = disk.clone();
+    damaged[512 + 40] ^= 0xFF;
+    damaged[(total - 1) * 512 + 40] ^= 0xFF;
+    expect_variant!(GuidPartitionTable::from(Cursor::new(damaged)), Err(gpt::Error::BadHeader));
+
+    let mut plain = vec![0u8; 2 * 512];
+    write_partition_entry(&mut plain, 0, 0, 0xC, 1, 1);
+    expect_variant!(GuidPartitionTable::from(Cursor::new(plain)), Err(gpt::Error::NoProtectiveMbr));
+}
+
+#[test]
+fn test_mount_gpt() {
+    let disk = gpt_disk(&first_volume(&resource_bytes!("mock1.fat32.img")));
+
+    // The first Basic Data partition holding FAT32 is mounted
+    let vfat = VFat::from(Cursor::new(disk.clone())).expect("mount GPT disk");
+    vfat.open("/HELLO.TXT").expect("file on the GPT partition");
+    VFat::from_partition(Cursor::new(disk.clone()), 1).expect("mount GPT partition 1");
+    expect_variant!(VFat::from_partition(Cursor::new(disk.clone()), 2), Err(::vfat::Error::NotFound));
+    // A Basic Data partition without a FAT32 volume
+    expect_variant!(VFat::from_partition(Cursor::new(disk.clone()), 0), Err(::vfat::Error::NotFound));
+
+    let mut damaged = disk.clone();
+    damaged[2 * 512 + 128 + 32] ^= 0xFF;
+    let vfat = VFat::from(Cursor::new(damaged)).expect("mount GPT disk from backup");
+    vfat.open("/HELLO.TXT").expect("file on the GPT partition");
+}
+
+// A blank disk with a new partition table around a copy of `volume`,
+// in bootable partition 1 starting at sector 4096
+fn with_new_mbr(volume: &[u8]) -> Vec<u8> {
+    let mut disk = vec![0u8; 4096 * 512];
+    disk.extend_from_slice(volume);
+    let mut mbr = MasterBootRecord::new(0xDEADBEEF);
+    mbr.partitions[1] = PartitionEntry::new(0xC, 4096, (volume.len() / 512) as u32);
+    mbr.partitions[1].set_bootable(true);
+    mbr.write_to(Cursor::new(&mut disk[..])).expect("write MBR");
+    disk
+}
+
+#[test]
+fn test_write_mbr() {
+    let chs = CHS::from_lba(0);
+    assert_eq!((chs.cylinder(), chs.head(), chs.sector()), (0, 0, 1));
+    let chs = CHS::from_lba(2048);
+    assert_eq!((chs.cylinder(), chs.head(), chs.sector()), (0, 32, 33));
+    let chs = CHS::from_lba(300 * 255 * 63 + 62);
+    assert_eq!((chs.cylinder(), chs.head(), chs.sector()), (300, 0, 63));
+    let chs = CHS::from_lba(1024 * 255 * 63);
+    assert_eq!((chs.cylinder(), chs.head(), chs.sector()), (1023, 254, 63));
+
+    let len = 16;
+    let mut disk = with_new_mbr(&vec![0; len * 512]);
+
+    assert_eq!(&disk[440..444], &[0xEF, 0xBE, 0xAD, 0xDE]);
+    assert_eq!(&disk[(446 + 16)..(446 + 21)], &[0x80, 65, 2, 0, 0xC]);
+    assert_eq!(&disk[510..512], &[0x55, 0xAA]);
+
+    let mbr = MasterBootRecord::from(Cursor::new(&mut disk[..])).expect("valid MBR");
+    assert_eq!({ mbr.disk_signature }, 0xDEADBEEF);
+    assert!(mbr.partitions[1].is_bootable());
+    assert!(!mbr.partitions[0].is_bootable());
+    let (first_chs, last_chs) = mbr.partitions[1].chs();
+    assert_eq!((first_chs.cylinder(), first_chs.head(), first_chs.sector()), (0, 65, 2));
+    let end = CHS::from_lba(4096 + len as u64 - 1);
+    assert_eq!((last_chs.cylinder(), last_chs.head(), last_chs.sector()), (end.cylinder(), end.head(), end.sector()));
+    assert_eq!(mbr.to_bytes(), &disk[..512]);
+
+    let mut mbr = mbr.clone();
+    mbr.partitions[3].bootable = 0x12;
+    expect_variant!(mbr.write_to(Cursor::new(&mut disk[..])), Err(::mbr::Error::UnknownBootIndicator(3)));
+}
+
+#[test]
+fn test_mount_new_mbr() {
+    let disk = with_new_mbr(&first_volume(&resource_bytes!("mock1.fat32.img")));
+    let vfat = VFat::from(Cursor::new(disk)).expect("mount new partition");
+    vfat.open("/HELLO.TXT").expect("file on the new partition");
+}
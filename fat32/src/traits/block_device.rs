@@ -8,6 +8,12 @@ pub trait BlockDevice: Send {
         512
     }
 
+    /// The number of sectors of the device, or `None` if it is unknown.
+    /// Defaults to `None`.
+    fn num_sectors(&mut self) -> Option<u64> {
+        None
+    }
+
     /// Read sector number `n` into `buf`.
     ///
     /// `self.sector_size()` or `buf.len()` bytes, whichever is less, are read
@@ -55,6 +61,10 @@ pub trait BlockDevice: Send {
 }
 
 impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
+    fn num_sectors(&mut self) -> Option<u64> {
+        (*self).num_sectors()
+    }
+
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
         (*self).read_sector(n, buf)
     }
@@ -68,6 +78,11 @@ macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
     use std::io::{Read, Write, Seek};
 
     impl $(<$($gen),*>)* BlockDevice for $T {
+        fn num_sectors(&mut self) -> Option<u64> {
+            let sector_size = self.sector_size();
+            self.seek(io::SeekFrom::End(0)).ok().map(|len| len / sector_size)
+        }
+
         fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
             let sector_size = self.sector_size();
             let to_read = ::std::cmp::min(sector_size as usize, buf.len());
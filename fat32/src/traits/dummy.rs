@@ -58,6 +58,7 @@ impl Timestamp for Dummy {
     fn hour(&self) -> u8 { panic!("Dummy") }
     fn minute(&self) -> u8 { panic!("Dummy") }
     fn second(&self) -> u8 { panic!("Dummy") }
+    fn millisecond(&self) -> u16 { panic!("Dummy") }
 }
 
 impl Metadata for Dummy {
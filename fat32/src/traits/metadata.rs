@@ -1,4 +1,5 @@
-/// Trait for a timestamp (year, month, day, hour, minute, second).
+/// Trait for a timestamp (year, month, day, hour, minute, second,
+/// millisecond).
 pub trait Timestamp: Copy + Clone + Sized {
     /// The calendar year.
     ///
@@ -21,6 +22,9 @@ pub trait Timestamp: Copy + Clone + Sized {
 
     /// The second. Always in range [0, 60).
     fn second(&self) -> u8;
+
+    /// The millisecond. Always in range [0, 1000).
+    fn millisecond(&self) -> u16;
 }
 
 /// Trait for directory entry metadata.
@@ -3,6 +3,7 @@ use std::{io, fmt};
 use std::collections::HashMap;
 
 use traits::BlockDevice;
+use vfat::{CachePolicy, Lru};
 
 #[derive(Debug)]
 struct CacheEntry {
@@ -17,10 +18,30 @@ pub struct Partition {
     pub sector_size: u64
 }
 
+/// Counters describing how effective the sector cache has been.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Accesses to sectors that were already cached.
+    pub hits: u64,
+    /// Accesses to sectors that were not cached.
+    pub misses: u64,
+    /// Sectors dropped from the cache to make room for others.
+    pub evictions: u64,
+    /// Dirty logical sectors written back to the device.
+    pub write_backs: u64,
+    /// Physical sectors read from the device.
+    pub physical_reads: u64,
+    /// Physical sectors written to the device.
+    pub physical_writes: u64
+}
+
 pub struct CachedDevice {
     device: Box<BlockDevice>,
     cache: HashMap<u64, CacheEntry>,
-    partition: Partition
+    partition: Partition,
+    capacity: Option<usize>, // Maximum number of cached sectors, if any
+    policy: Box<CachePolicy>, // Picks the sector to evict when full
+    stats: CacheStats
 }
 
 impl CachedDevice {
@@ -51,8 +72,66 @@ impl CachedDevice {
         CachedDevice {
             device: Box::new(device),
             cache: HashMap::new(),
-            partition: partition
+            partition: partition,
+            capacity: None,
+            policy: Box::new(Lru::new()),
+            stats: CacheStats::default()
+        }
+    }
+
+    /// Creates a new `CachedDevice` like `new()`, but keeps at most
+    /// `capacity` sectors in memory at any time.
+    ///
+    /// When a sector that is not cached is accessed while the cache is full,
+    /// the least recently used sector is evicted. Dirty sectors are written
+    /// back to `device` before being evicted. To bound the cache by a number
+    /// of bytes instead, divide it by `partition.sector_size`. Use
+    /// `set_policy()` to pick another replacement strategy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the partition's sector size is < the device's sector size or
+    /// if `capacity` is 0.
+    pub fn with_capacity<T>(device: T, partition: Partition, capacity: usize) -> CachedDevice
+        where T: BlockDevice + 'static
+    {
+        assert!(capacity > 0);
+
+        let mut cached = CachedDevice::new(device, partition);
+        cached.capacity = Some(capacity);
+        cached
+    }
+
+    /// Bounds the cache to `capacity` sectors and evicts sectors according
+    /// to `policy` from now on. Sectors that are already cached are handed
+    /// over to `policy`, and sectors are evicted until at most `capacity` of
+    /// them remain.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing back an evicted dirty sector fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn set_policy<P>(&mut self, capacity: usize, policy: P) -> io::Result<()>
+        where P: CachePolicy + 'static
+    {
+        assert!(capacity > 0);
+
+        let mut policy = Box::new(policy);
+        let mut sectors: Vec<u64> = self.cache.keys().cloned().collect();
+        sectors.sort();
+        for sector in sectors {
+            policy.inserted(sector);
         }
+
+        self.policy = policy;
+        self.capacity = Some(capacity);
+        while self.cache.len() > capacity {
+            self.evict()?;
+        }
+        Ok(())
     }
 
     /// Maps a user's request for a sector `virt` to the physical sector and
@@ -71,24 +150,132 @@ impl CachedDevice {
         }
     }
 
+    // Size of the cached copy of sector `sector`: physical sectors
+    // before the partition, logical sectors inside of it
+    fn cached_sector_size(&self, sector: u64) -> u64 {
+        if sector < self.partition.start {
+            self.device.sector_size()
+        } else {
+            self.partition.sector_size
+        }
+    }
+
     // Ensure that `sector` is read and inside cache
     fn ensure_cache(&mut self, sector: u64) -> io::Result<()> {
-        if !self.cache.contains_key(&sector) {
-            let mut buf = vec![0u8; self.partition.sector_size as usize];
+        if self.cache.contains_key(&sector) {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+            let mut buf = vec![0u8; self.cached_sector_size(sector) as usize];
             let device_sector_size = self.device.sector_size() as usize;
             let (device_sector, num) = self.virtual_to_physical(sector);
             for i in 0..(num as usize) {
                 let start = i * device_sector_size;
                 self.device.read_sector(device_sector + i as u64, &mut buf[start..(start + device_sector_size)])?;
+                self.stats.physical_reads += 1;
             }
-            self.cache.insert(sector, CacheEntry {
-                data: buf,
-                dirty: false
-            });
+            self.insert(sector, buf, false)?;
         }
         Ok(())
     }
 
+    // Put a new sector into the cache, making room for it first
+    // if the cache is already full
+    fn insert(&mut self, sector: u64, data: Vec<u8>, dirty: bool) -> io::Result<()> {
+        if let Some(capacity) = self.capacity {
+            while self.cache.len() >= capacity {
+                self.evict()?;
+            }
+        }
+
+        self.cache.insert(sector, CacheEntry {
+            data,
+            dirty
+        });
+        self.policy.inserted(sector);
+        Ok(())
+    }
+
+    // Remove the sector chosen by the replacement policy from the cache
+    // writing it back first if it is dirty
+    fn evict(&mut self) -> io::Result<()> {
+        match self.policy.victim() {
+            Some(sector) => {
+                self.write_back(sector)?;
+                self.cache.remove(&sector);
+                self.policy.removed(sector);
+                self.stats.evictions += 1;
+                Ok(())
+            },
+            None => Err(io::Error::new(io::ErrorKind::Other, "Cache policy has no sector to evict"))
+        }
+    }
+
+    // Ensure that `sector` is cached and record the access for the policy
+    fn touch(&mut self, sector: u64) -> io::Result<&mut CacheEntry> {
+        self.ensure_cache(sector)?;
+        self.policy.accessed(sector);
+        Ok(self.cache.get_mut(&sector).unwrap())
+    }
+
+    // Write the cached copy of `sector` back to the device
+    // splitting the logical sector into as many physical sectors
+    // as needed. The entry is marked clean afterwards.
+    fn write_back(&mut self, sector: u64) -> io::Result<()> {
+        let device_sector_size = self.device.sector_size() as usize;
+        let (device_sector, num) = self.virtual_to_physical(sector);
+        let entry = match self.cache.get_mut(&sector) {
+            Some(entry) => entry,
+            None => return Ok(())
+        };
+
+        if !entry.dirty {
+            return Ok(());
+        }
+
+        for i in 0..(num as usize) {
+            let start = i * device_sector_size;
+            self.device.write_sector(device_sector + i as u64, &entry.data[start..(start + device_sector_size)])?;
+            self.stats.physical_writes += 1;
+        }
+        entry.dirty = false;
+        self.stats.write_backs += 1;
+        Ok(())
+    }
+
+    /// Writes every dirty sector in the cache back to the underlying device.
+    /// Sectors stay cached after being written back.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error writing a sector to the disk.
+    /// Sectors that were not written back successfully stay dirty.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut dirty: Vec<u64> = self.cache.iter()
+            .filter(|&(_, entry)| entry.dirty)
+            .map(|(sector, _)| *sector)
+            .collect();
+
+        // Write back in ascending order so that the device sees
+        // (mostly) sequential writes
+        dirty.sort();
+        for sector in dirty {
+            self.write_back(sector)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the counters collected since the device was created or since
+    /// the last call to `reset_stats()`.
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Resets all counters returned by `stats()` to zero.
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
     /// Returns a mutable reference to the cached sector `sector`. If the sector
     /// is not already cached, the sector is first read from the disk.
     ///
@@ -100,8 +287,9 @@ impl CachedDevice {
     ///
     /// Returns an error if there is an error reading the sector from the disk.
     pub fn get_mut(&mut self, sector: u64) -> io::Result<&mut [u8]> {
-        self.ensure_cache(sector)?;
-        Ok(&mut self.cache.get_mut(&sector).unwrap().data)
+        let entry = self.touch(sector)?;
+        entry.dirty = true;
+        Ok(&mut entry.data)
     }
 
     /// Returns a reference to the cached sector `sector`. If the sector is not
@@ -111,41 +299,44 @@ impl CachedDevice {
     ///
     /// Returns an error if there is an error reading the sector from the disk.
     pub fn get(&mut self, sector: u64) -> io::Result<&[u8]> {
-        self.ensure_cache(sector)?;
-        Ok(&self.cache.get_mut(&sector).unwrap().data)
+        Ok(&self.touch(sector)?.data)
     }
 }
 
-// FIXME: Implement `BlockDevice` for `CacheDevice`. The `read_sector` and
-// `write_sector` methods should only read/write from/to cached sectors.
+// The `read_sector` and `write_sector` methods only read/write from/to
+// cached sectors. Dirty sectors reach the device on `flush()`.
 impl BlockDevice for CachedDevice {
     fn sector_size(&self) -> u64 {
         self.partition.sector_size
     }
 
     fn read_sector(&mut self, n: u64, mut buf: &mut [u8]) -> io::Result<usize> {
-        let len = cmp::min(buf.len(), self.partition.sector_size as usize);
+        let len = cmp::min(buf.len(), self.cached_sector_size(n) as usize);
         let mut sector = &self.get(n)?[..len];
         io::copy(&mut sector, &mut buf)?;
         Ok(len as usize)
     }
 
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("BlockDevice::write() unimplemented!");
-        /*let len = cmp::min(buf.len() as u64, self.partition.sector_size);
-
-        if self.cache.contains_key(&n) {
-            let cache_entry = self.cache.get_mut(&n).unwrap();
-            cache_entry.dirty = true;
-            //cache_entry.data[..].clone_from_slice(buf);
-            cache_entry.data[..len].clone_from_slice(buf);
+        let sector_size = self.cached_sector_size(n) as usize;
+        let len = cmp::min(buf.len(), sector_size);
+
+        if len == sector_size && !self.cache.contains_key(&n) {
+            // The whole sector is overwritten, no need to read it first
+            self.stats.misses += 1;
+            self.insert(n, buf[..len].to_vec(), true)?;
         } else {
-            self.cache.insert(n, CacheEntry {
-                data: Vec::from(buf.clone()),
-                dirty: true
-            });
+            self.get_mut(n)?[..len].copy_from_slice(&buf[..len]);
         }
-        Ok(buf.len())*/
+        Ok(len)
+    }
+}
+
+impl Drop for CachedDevice {
+    fn drop(&mut self) {
+        // Nothing can be reported from here; callers who care about
+        // errors should call `flush()` explicitly before dropping.
+        let _ = self.flush();
     }
 }
 
@@ -154,6 +345,9 @@ impl fmt::Debug for CachedDevice {
         f.debug_struct("CachedDevice")
             .field("device", &"<block device>")
             .field("cache", &self.cache)
+            .field("capacity", &self.capacity)
+            .field("policy", &self.policy)
+            .field("stats", &self.stats)
             .finish()
     }
 }
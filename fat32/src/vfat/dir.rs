@@ -1,21 +1,26 @@
 use std::ffi::OsStr;
-use std::str::from_utf8_unchecked;
 use std::char::{decode_utf16, REPLACEMENT_CHARACTER};
 use std::borrow::Cow;
 use std::io;
 use std::cmp::{Ord, Ordering};
+use std::mem::size_of;
+use std::slice;
 
 use traits;
 use util::VecExt;
 use vfat::{VFat, VFatExt, Shared, File, Cluster, Entry};
-use vfat::{Metadata, Attributes, Timestamp, Time, Date};
+use vfat::{Metadata, Attributes, Timestamp, Time, Date, CodePage};
+use vfat::name;
 
 #[derive(Debug)]
 pub struct Dir {
     drive: Shared<VFat>,
     cluster: Cluster,
     pub name: String,
-    pub metadata: Metadata
+    pub short_name: String, // The 8.3 alias of `name`
+    pub long_name: Option<String>, // The name stored in LFN entries, if any
+    pub metadata: Metadata,
+    pub entry: Option<EntryLocation> // `None` for the root directory
 }
 
 #[repr(C, packed)]
@@ -24,8 +29,8 @@ pub struct VFatRegularDirEntry {
     name: [u8; 8],
     extension: [u8; 3],
     attribute: Attributes,
-    _reserved: u8,
-    _creation_time_tenth: u8, // Creation time in tenths of a second
+    nt_case: u8, // Case of the 8.3 name, see `name::LOWERCASE_BASE` and `name::LOWERCASE_EXTENSION`
+    create_time_centiseconds: u8, // Creation time in 10 ms units (0-199) on top of `create_time`
     create_time: Time,
     create_date: Date,
     last_access_date: Date,
@@ -36,6 +41,164 @@ pub struct VFatRegularDirEntry {
     size: u32
 }
 
+impl VFatRegularDirEntry {
+    /// Creates an empty entry with the given attributes. All other fields,
+    /// including the name, are zeroed.
+    pub fn new(attribute: Attributes) -> VFatRegularDirEntry {
+        VFatRegularDirEntry {
+            name: [0; 8],
+            extension: [0; 3],
+            attribute,
+            nt_case: 0,
+            create_time_centiseconds: 0,
+            create_time: Time::empty(),
+            create_date: Date::empty(),
+            last_access_date: Date::empty(),
+            first_cluster_high: 0,
+            last_modification_time: Time::empty(),
+            last_modification_date: Date::empty(),
+            first_cluster_low: 0,
+            size: 0
+        }
+    }
+
+    pub fn short_name(&self) -> [u8; 11] {
+        let mut short = [0u8; 11];
+        short[..8].copy_from_slice(&self.name);
+        short[8..].copy_from_slice(&self.extension);
+        short
+    }
+
+    /// Decodes the 8.3 name as `NAME.EXT` using `code_page`. If `nt_case` is
+    /// set, the parts flagged as lower case by the NT case byte are lowered.
+    pub fn decode_short_name(&self, code_page: &CodePage, nt_case: bool) -> String {
+        let mut name = code_page.decode(&self.name);
+        if nt_case && self.nt_case & name::LOWERCASE_BASE != 0 {
+            name = name.to_ascii_lowercase();
+        }
+        if self.extension[0] != 0x00 && self.extension[0] != 0x20 {
+            let mut extension = code_page.decode(&self.extension);
+            if nt_case && self.nt_case & name::LOWERCASE_EXTENSION != 0 {
+                extension = extension.to_ascii_lowercase();
+            }
+            name = format!("{}.{}", name, extension);
+        }
+        name
+    }
+
+    pub fn set_short_name(&mut self, short: &[u8; 11]) {
+        self.name.copy_from_slice(&short[..8]);
+        self.extension.copy_from_slice(&short[8..]);
+    }
+
+    pub fn cluster(&self) -> Cluster {
+        Cluster::from(((self.first_cluster_high as u32) << 16) + self.first_cluster_low as u32)
+    }
+
+    pub fn set_cluster(&mut self, cluster: Cluster) {
+        self.first_cluster_high = (cluster.get() >> 16) as u16;
+        self.first_cluster_low = (cluster.get() & 0xFFFF) as u16;
+    }
+
+    pub fn set_size(&mut self, size: u32) {
+        self.size = size;
+    }
+
+    pub fn attribute(&self) -> Attributes {
+        self.attribute
+    }
+
+    /// The attributes and timestamps of the entry.
+    pub fn metadata(&self) -> Metadata {
+        Metadata {
+            attributes: self.attribute,
+            created: Timestamp {
+                date: self.create_date,
+                time: Some(self.create_time),
+                centiseconds: self.create_time_centiseconds
+            },
+            last_accessed: Timestamp::date_only(self.last_access_date),
+            last_modified: Timestamp::new(self.last_modification_date, self.last_modification_time)
+        }
+    }
+
+    /// Sets the creation, last access and last modification timestamps to
+    /// `now`.
+    pub fn set_created(&mut self, now: &Timestamp) {
+        self.create_date = now.date;
+        self.create_time = now.time.unwrap_or(Time::empty());
+        self.create_time_centiseconds = now.centiseconds;
+        self.set_modified(now);
+    }
+
+    /// Sets the last access and last modification timestamps to `now`.
+    pub fn set_modified(&mut self, now: &Timestamp) {
+        self.last_access_date = now.date;
+        self.last_modification_date = now.date;
+        self.last_modification_time = now.time.unwrap_or(Time::empty());
+    }
+
+    pub fn set_attribute(&mut self, attribute: Attributes) {
+        self.attribute = attribute;
+    }
+}
+
+/// The position of a regular directory entry on disk: the first cluster of
+/// the directory containing it and its byte offset in that cluster chain.
+/// `start` is the offset of the first LFN entry belonging to the entry, or
+/// `offset` if it has none.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EntryLocation {
+    pub dir: Cluster,
+    pub start: usize,
+    pub offset: usize
+}
+
+impl EntryLocation {
+    /// Reads the regular directory entry at this location.
+    pub fn read(&self, drive: &Shared<VFat>) -> io::Result<VFatRegularDirEntry> {
+        let mut buf = [0u8; 32];
+        if drive.read_cluster(self.dir, self.offset, &mut buf)? != buf.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Directory entry out of range"));
+        }
+        Ok(unsafe { *(buf.as_ptr() as *const VFatRegularDirEntry) })
+    }
+
+    /// Overwrites the regular directory entry at this location with `entry`.
+    pub fn write(&self, drive: &Shared<VFat>, entry: &VFatRegularDirEntry) -> io::Result<()> {
+        drive.write_cluster(self.dir, self.offset, entry_bytes(entry))?;
+        Ok(())
+    }
+
+    /// Replaces the attributes of the entry listed in `Attributes::MUTABLE` by
+    /// the ones in `attributes` and returns the resulting attributes. Other
+    /// attributes (`DIRECTORY`, `VOLUME_ID`) are kept as is.
+    pub fn set_attributes(&self, drive: &Shared<VFat>, attributes: Attributes) -> io::Result<Attributes> {
+        let mut entry = self.read(drive)?;
+        let kept = entry.attribute.raw() & !Attributes::MUTABLE;
+        entry.attribute = Attributes::from(kept | (attributes.raw() & Attributes::MUTABLE));
+        self.write(drive, &entry)?;
+        Ok(entry.attribute)
+    }
+
+    /// Marks the regular entry and all of its LFN entries as deleted.
+    pub fn delete(&self, drive: &Shared<VFat>) -> io::Result<()> {
+        let mut offset = self.start;
+        while offset <= self.offset {
+            drive.write_cluster(self.dir, offset, &[0xE5])?;
+            offset += 32;
+        }
+        Ok(())
+    }
+}
+
+// View an on-disk directory entry structure as raw bytes
+fn entry_bytes<T>(entry: &T) -> &[u8] {
+    unsafe {
+        slice::from_raw_parts(entry as *const T as *const u8, size_of::<T>())
+    }
+}
+
 #[repr(C, packed)]
 #[derive(Debug, Copy, Clone)]
 pub struct VFatLfnDirEntry {
@@ -49,6 +212,31 @@ pub struct VFatLfnDirEntry {
     name3: [u16; 2], // should be appended to the second, same termination rule
 }
 
+impl VFatLfnDirEntry {
+    /// Creates the LFN entry with sequence number `seq` (starting at 1)
+    /// holding the characters `chars`. `last` marks the entry holding the
+    /// end of the name.
+    pub fn new(seq: u8, last: bool, checksum: u8, chars: &[u16; 13]) -> VFatLfnDirEntry {
+        let mut name = [0u16; 5];
+        let mut name2 = [0u16; 6];
+        let mut name3 = [0u16; 2];
+        name.copy_from_slice(&chars[0..5]);
+        name2.copy_from_slice(&chars[5..11]);
+        name3.copy_from_slice(&chars[11..]);
+
+        VFatLfnDirEntry {
+            seq_number: if last { seq | 0x40 } else { seq },
+            name,
+            attribute: Attributes::from(Attributes::LFN),
+            dir_type: 0,
+            checksum,
+            name2,
+            _reserved: 0,
+            name3
+        }
+    }
+}
+
 #[repr(C, packed)]
 #[derive(Debug, Copy, Clone)]
 pub struct VFatUnknownDirEntry {
@@ -77,31 +265,19 @@ pub enum VFatDirEntrySafe {
  * `VFatDirEntrySafe`.
  */
 unsafe fn parse_dir_entry(ent: &VFatDirEntry) -> VFatDirEntrySafe {
-    if ent.unknown.attribute.equal_to(Attributes::LFN) {
-        VFatDirEntrySafe::Lfn(ent.long_filename.clone())
-    } else if ent.unknown.flag == 0xE5 {
+    // Deleted LFN entries are marked the same way as
+    // regular ones, so check for deletion first
+    if ent.unknown.flag == 0xE5 {
         VFatDirEntrySafe::Deleted
     } else if ent.unknown.flag == 0x00 {
         VFatDirEntrySafe::End
+    } else if ent.unknown.attribute.equal_to(Attributes::LFN) {
+        VFatDirEntrySafe::Lfn(ent.long_filename.clone())
     } else {
         VFatDirEntrySafe::Regular(ent.regular.clone())
     }
 }
 
-// Decode file name from regular entries
-// Since they are all ASCII characters, we don't need
-// to do any check on this.
-// Regular file names can be early-terminated by 0x00 or 0x20
-fn decode_file_name_utf8_ascii(name: &[u8]) -> String {
-    unsafe {
-        from_utf8_unchecked(
-            &name.iter()
-                .map(|x| *x)
-                .take_while(|x| *x != 0x00 && *x != 0x20)
-                .collect::<Vec<u8>>()[..]).to_string()
-    }
-}
-
 // Decode LFN file names (UTF16)
 // For unknown characters, replace them with replacement char
 // LFNs can be early terminated with 0x00 or 0xFF
@@ -112,23 +288,56 @@ fn decode_file_name_utf16(name: &[u16]) -> String {
 }
 
 impl Dir {
+    /// The 8.3 alias of the name of `self`, empty for the root directory.
+    pub fn short_name(&self) -> &str {
+        &self.short_name
+    }
+
+    /// The name stored in LFN entries, or `None` if `self` only has an 8.3
+    /// name.
+    pub fn long_name(&self) -> Option<&str> {
+        self.long_name.as_ref().map(|name| name.as_str())
+    }
+
+    /// The attribute byte of the directory entry of `self`.
+    pub fn attributes(&self) -> u8 {
+        self.metadata.attributes.raw()
+    }
+
+    /// Changes the attributes of `self` on disk, see
+    /// `EntryLocation::set_attributes()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` for the root directory, which has no
+    /// attributes.
+    pub fn set_attributes(&mut self, attributes: Attributes) -> io::Result<()> {
+        let location = self.entry
+            .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "The root directory has no attributes"))?;
+        self.metadata.attributes = location.set_attributes(&self.drive, attributes)?;
+        Ok(())
+    }
+
     pub fn from_root_cluster(drive: Shared<VFat>, cluster: Cluster) -> Dir {
         Dir {
             drive,
             cluster,
             name: "".to_string(),
+            short_name: "".to_string(),
+            long_name: None,
             metadata: Metadata {
-                is_read_only: false,
-                is_hidden: false,
+                attributes: Attributes::from(Attributes::DIRECTORY),
                 created: Timestamp::empty(),
                 last_accessed: Timestamp::empty(),
                 last_modified: Timestamp::empty()
-            }
+            },
+            entry: None
         }
     }
 
-    /// Finds the entry named `name` in `self` and returns it. Comparison is
-    /// case-insensitive.
+    /// Finds the entry named `name` in `self` and returns it. Both the long
+    /// name and the 8.3 alias of every entry are compared with `name`.
+    /// Comparison is case-insensitive, using Unicode simple case folding.
     ///
     /// # Errors
     ///
@@ -141,111 +350,510 @@ impl Dir {
         use traits::{Dir, Entry};
         let name = name.as_ref().to_str().ok_or(io::Error::new(io::ErrorKind::InvalidInput, "Invalid file name"))?;
         for dir in self.entries()? {
-            if dir.name().eq_ignore_ascii_case(name) {
+            if name::eq_ignore_case(dir.name(), name) || name::eq_ignore_case(dir.short_name(), name) {
                 return Ok(dir);
             }
         }
         return Err(io::Error::new(io::ErrorKind::NotFound, "File not found"));
     }
+
+    // Check that `name` is usable for a new entry in `self`
+    fn check_new_name<'b>(&self, name: &'b OsStr) -> io::Result<&'b str> {
+        let name = name.to_str().ok_or(io::Error::new(io::ErrorKind::InvalidInput, "Invalid file name"))?;
+        if !name::is_valid_long_name(name) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid file name"));
+        }
+
+        match self.find(name) {
+            Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, "File already exists")),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(name),
+            Err(e) => Err(e)
+        }
+    }
+
+    // Short names of every entry in `self`
+    // except for the one at `except`, if any
+    fn short_names(&self, except: Option<EntryLocation>) -> io::Result<Vec<[u8; 11]>> {
+        let mut buf = Vec::new();
+        self.drive.read_chain(self.cluster, &mut buf)?;
+
+        let skipped = match except {
+            Some(location) if location.dir == self.cluster => Some(location.offset),
+            _ => None
+        };
+
+        let mut names = Vec::new();
+        for (i, raw) in buf.chunks(32).enumerate() {
+            if raw.len() < 32 || raw[0] == 0x00 {
+                break;
+            }
+            if skipped == Some(i * 32) {
+                continue;
+            }
+            let ent = unsafe { parse_dir_entry(&*(raw.as_ptr() as *const VFatDirEntry)) };
+            if let VFatDirEntrySafe::Regular(regular) = ent {
+                names.push(regular.short_name());
+            }
+        }
+        Ok(names)
+    }
+
+    // Find room for `count` consecutive entries in `self`
+    // and return the byte offset of the first one.
+    // The cluster chain is extended with zeroed clusters
+    // if there is not enough room.
+    fn find_free_slots(&self, count: usize) -> io::Result<usize> {
+        let mut buf = Vec::new();
+        self.drive.read_chain(self.cluster, &mut buf)?;
+
+        // Entries at and after an end marker (0x00) are all free
+        let total = buf.len() / 32;
+        let mut end = total;
+        let mut run_start = 0;
+        let mut run_len = 0;
+        for i in 0..total {
+            match buf[i * 32] {
+                0x00 => {
+                    end = i;
+                    break;
+                },
+                0xE5 => {
+                    if run_len == 0 {
+                        run_start = i;
+                    }
+                    run_len += 1;
+                    if run_len == count {
+                        return Ok(run_start * 32);
+                    }
+                },
+                _ => run_len = 0
+            }
+        }
+
+        // A run of deleted entries right before the end
+        // can be merged into the free space after it
+        if run_len == 0 {
+            run_start = end;
+        }
+
+        let available = total - run_start;
+        if available < count {
+            let cluster_bytes = self.drive.borrow().bytes_per_cluster();
+            let missing = (count - available) * 32;
+            let clusters = (missing + cluster_bytes - 1) / cluster_bytes;
+            self.drive.write_cluster(self.cluster, buf.len(), &vec![0u8; clusters * cluster_bytes])?;
+        } else if run_start + count > end && run_start + count < total && buf[(run_start + count) * 32] != 0x00 {
+            // Leftovers after the old end marker must not
+            // show up as entries after the new ones
+            self.drive.write_cluster(self.cluster, (run_start + count) * 32, &[0x00])?;
+        }
+        Ok(run_start * 32)
+    }
+
+    // Write `entry` into `self` under `name`
+    // An 8.3 name is generated for `entry`, and the full `name`
+    // is stored in LFN entries preceeding it unless the 8.3 name
+    // and its case flags are enough to represent it.
+    // `replaced` is the location of an entry that is about
+    // to be deleted, whose 8.3 name may be reused.
+    fn insert_entry(
+        &self,
+        name: &str,
+        entry: &mut VFatRegularDirEntry,
+        replaced: Option<EntryLocation>
+    ) -> io::Result<EntryLocation> {
+        let code_page = self.drive.borrow().code_page();
+        let short = name::short_name(name, &self.short_names(replaced)?, &code_page);
+        entry.set_short_name(&short);
+
+        let chunks = match name::case_flags(name, &short, &code_page) {
+            Some(flags) => {
+                entry.nt_case = flags;
+                Vec::new()
+            },
+            None => {
+                entry.nt_case = 0;
+                name::lfn_chunks(name)
+            }
+        };
+        let checksum = name::lfn_checksum(&short);
+        let offset = self.find_free_slots(chunks.len() + 1)?;
+
+        // LFN entries are stored in reverse order
+        let mut buf = Vec::with_capacity((chunks.len() + 1) * 32);
+        for (i, chunk) in chunks.iter().enumerate().rev() {
+            let lfn = VFatLfnDirEntry::new((i + 1) as u8, i + 1 == chunks.len(), checksum, chunk);
+            buf.extend_from_slice(entry_bytes(&lfn));
+        }
+        buf.extend_from_slice(entry_bytes(entry));
+        self.drive.write_cluster(self.cluster, offset, &buf)?;
+
+        Ok(EntryLocation {
+            dir: self.cluster,
+            start: offset,
+            offset: offset + chunks.len() * 32
+        })
+    }
+
+    /// Creates a new, empty file named `name` in `self` and returns it.
+    ///
+    /// # Errors
+    ///
+    /// If `name` is not a valid file name, an error of `InvalidInput` is
+    /// returned.
+    ///
+    /// If an entry named `name` already exists, an error of `AlreadyExists`
+    /// is returned.
+    pub fn create_file<P: AsRef<OsStr>>(&self, name: P) -> io::Result<File> {
+        let name = self.check_new_name(name.as_ref())?;
+        let mut entry = VFatRegularDirEntry::new(Attributes::from(Attributes::ARCHIVE));
+        if let Some(now) = self.drive.borrow().now() {
+            entry.set_created(&now);
+        }
+        let location = self.insert_entry(name, &mut entry, None)?;
+        let code_page = self.drive.borrow().code_page();
+
+        Ok(File {
+            drive: self.drive.clone(),
+            cluster: Cluster::from(0),
+            name: name.to_string(),
+            short_name: entry.decode_short_name(&code_page, false),
+            long_name: if location.start < location.offset { Some(name.to_string()) } else { None },
+            metadata: entry.metadata(),
+            size: 0,
+            offset: 0,
+            entry: location,
+            dirty: false
+        })
+    }
+
+    /// Removes the entry named `name` from `self`. If the entry is a directory
+    /// and `children` is `true`, everything inside of it is removed
+    /// recursively.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound` is
+    /// returned.
+    ///
+    /// If `name` is `.` or `..`, an error of `InvalidInput` is returned.
+    ///
+    /// If the entry is a non-empty directory and `children` is `false`, an
+    /// error of `Other` is returned.
+    pub fn remove<P: AsRef<OsStr>>(&self, name: P, children: bool) -> io::Result<()> {
+        let name = name.as_ref();
+        if name == "." || name == ".." {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Cannot remove . or .."));
+        }
+        remove_entry(self.find(name)?, children)
+    }
+
+    /// Moves the entry named `from` in `self` to the directory `to_dir`,
+    /// under the name `to`. `to_dir` may be `self`. Only the directory entry
+    /// is rewritten, the content of the file or directory stays in place. The
+    /// `..` entry of a directory moved to another parent is updated.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `from` exists in `self`, an error of `NotFound`
+    /// is returned.
+    ///
+    /// If `from` is `.` or `..`, `to` is not a valid file name, or a
+    /// directory would be moved inside of itself, an error of `InvalidInput`
+    /// is returned.
+    ///
+    /// If a different entry named `to` already exists in `to_dir`, an error
+    /// of `AlreadyExists` is returned.
+    pub fn rename<P, Q>(&self, from: P, to_dir: &Dir, to: Q) -> io::Result<()>
+        where P: AsRef<OsStr>, Q: AsRef<OsStr>
+    {
+        let from = from.as_ref();
+        if from == "." || from == ".." {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Cannot rename . or .."));
+        }
+
+        let entry = self.find(from)?;
+        let location = entry.location()
+            .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "Cannot rename the root directory"))?;
+
+        let to = to.as_ref();
+        let name = match to_dir.check_new_name(to) {
+            Ok(name) => name,
+            // Only the case of the name changes
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists
+                && to_dir.find(to)?.location() == Some(location) => to.to_str().unwrap(),
+            Err(e) => return Err(e)
+        };
+
+        let moved_dir = match entry {
+            ::vfat::Entry::Dir(ref dir) if to_dir.cluster != self.cluster => Some(dir.cluster),
+            _ => None
+        };
+        if let Some(moved) = moved_dir {
+            to_dir.check_not_inside(moved)?;
+        }
+
+        let mut raw = location.read(&self.drive)?;
+        to_dir.insert_entry(name, &mut raw, Some(location))?;
+        location.delete(&self.drive)?;
+
+        if let Some(moved) = moved_dir {
+            let dot_dot = EntryLocation { dir: moved, start: 32, offset: 32 };
+            let mut raw = dot_dot.read(&self.drive)?;
+            raw.set_cluster(to_dir.dot_dot_cluster());
+            dot_dot.write(&self.drive, &raw)?;
+        }
+        Ok(())
+    }
+
+    // Fail if `self` is the directory starting at `cluster`
+    // or any of its descendants, by walking up the `..` entries
+    fn check_not_inside(&self, cluster: Cluster) -> io::Result<()> {
+        let root_cluster = self.drive.borrow().root_cluster();
+        let mut cur = self.cluster;
+        while cur != root_cluster {
+            if cur == cluster {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Cannot move a directory inside of itself"));
+            }
+            let parent = EntryLocation { dir: cur, start: 32, offset: 32 }.read(&self.drive)?.cluster();
+            cur = if parent.get() == 0 { root_cluster } else { parent };
+        }
+        Ok(())
+    }
+
+    // The cluster that `..` entries of subdirectories of `self` refer to
+    fn dot_dot_cluster(&self) -> Cluster {
+        // `..` refers to the root directory as cluster 0
+        if self.cluster == self.drive.borrow().root_cluster() {
+            Cluster::from(0)
+        } else {
+            self.cluster
+        }
+    }
+
+    /// Creates a new, empty directory named `name` in `self` and returns it.
+    ///
+    /// The directory gets a freshly allocated, zeroed cluster holding its `.`
+    /// and `..` entries.
+    ///
+    /// # Errors
+    ///
+    /// If `name` is not a valid file name, an error of `InvalidInput` is
+    /// returned.
+    ///
+    /// If an entry named `name` already exists, an error of `AlreadyExists`
+    /// is returned.
+    pub fn create_dir<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Dir> {
+        let name = self.check_new_name(name.as_ref())?;
+
+        let cluster = self.drive.borrow_mut().alloc_cluster(None)?;
+        let cluster_bytes = self.drive.borrow().bytes_per_cluster();
+        let parent_cluster = self.dot_dot_cluster();
+        let now = self.drive.borrow().now();
+
+        let mut entry = VFatRegularDirEntry::new(Attributes::from(Attributes::DIRECTORY));
+        entry.set_cluster(cluster);
+        if let Some(ref now) = now {
+            entry.set_created(now);
+        }
+
+        // `.` and `..` carry the same timestamps as the new directory
+        let mut dot = entry;
+        dot.set_short_name(b".          ");
+        let mut dot_dot = entry;
+        dot_dot.set_short_name(b"..         ");
+        dot_dot.set_cluster(parent_cluster);
+
+        let mut buf = vec![0u8; cluster_bytes];
+        buf[..32].copy_from_slice(entry_bytes(&dot));
+        buf[32..64].copy_from_slice(entry_bytes(&dot_dot));
+        let inserted = self.drive.write_cluster(cluster, 0, &buf)
+            .and_then(|_| self.insert_entry(name, &mut entry, None));
+        let location = match inserted {
+            Ok(location) => location,
+            Err(e) => {
+                // Give the cluster back, the original error is the one to report
+                let _ = self.drive.borrow_mut().free_chain(cluster);
+                return Err(e);
+            }
+        };
+        let code_page = self.drive.borrow().code_page();
+
+        Ok(Dir {
+            drive: self.drive.clone(),
+            cluster,
+            name: name.to_string(),
+            short_name: entry.decode_short_name(&code_page, false),
+            long_name: if location.start < location.offset { Some(name.to_string()) } else { None },
+            metadata: entry.metadata(),
+            entry: Some(location)
+        })
+    }
+}
+
+// Delete `entry` and free its clusters
+// The content of directories is removed first if `children` is set
+fn remove_entry(entry: Entry, children: bool) -> io::Result<()> {
+    use traits::{Dir, Entry};
+
+    let (drive, cluster, location) = match entry {
+        ::vfat::Entry::File(file) => (file.drive, file.cluster, file.entry),
+        ::vfat::Entry::Dir(dir) => {
+            let location = dir.entry
+                .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "Cannot remove the root directory"))?;
+
+            // `.` and `..` must not be followed, they are not children
+            let mut content = dir.entries()?
+                .filter(|e| e.name() != "." && e.name() != "..")
+                .peekable();
+            if content.peek().is_some() && !children {
+                return Err(io::Error::new(io::ErrorKind::Other, "Directory is not empty"));
+            }
+            for child in content {
+                remove_entry(child, true)?;
+            }
+            (dir.drive, dir.cluster, location)
+        }
+    };
+
+    location.delete(&drive)?;
+    if cluster.get() != 0 {
+        drive.borrow_mut().free_chain(cluster)?;
+    }
+    Ok(())
 }
 
 // Record of all LFNs preceeding a regular entry
 // the full file name can be decoded when all the LFNs are found
 // needed when finally constructing the entry structure
+// Only a single well-formed sequence is kept: it has to start
+// with the entry flagged as last, continue with decreasing
+// sequence numbers down to 1 and carry the same checksum.
 struct LfnList {
     // (sequence_number, file_name_characters)
     // characters are UTF16
-    buf: Vec<(u8, [u16; 13])>
+    buf: Vec<(u8, [u16; 13])>,
+    checksum: u8, // Checksum carried by every entry of the sequence
+    start: usize // Offset of the first entry of the sequence
 }
 
 impl LfnList {
     fn new() -> LfnList {
         LfnList {
-            buf: Vec::new()
+            buf: Vec::new(),
+            checksum: 0,
+            start: 0
         }
     }
 
-    // Add a new entry into LFN list
-    fn push(&mut self, lfn: VFatLfnDirEntry) {
-        let seq = lfn.seq_number & 0x0F;
+    // Add a new entry found at `offset` into LFN list
+    // Entries that don't continue the current sequence
+    // invalidate it.
+    fn push(&mut self, lfn: VFatLfnDirEntry, offset: usize) {
+        let seq = lfn.seq_number & 0x1F;
         let mut name_buf = [0u16; 13];
         name_buf[0..5].clone_from_slice(&lfn.name[..]);
         name_buf[5..11].clone_from_slice(&lfn.name2[..]);
         name_buf[11..].clone_from_slice(&lfn.name3[..]);
-        self.buf.push((seq, name_buf));
-    }
 
-    fn is_empty(&self) -> bool {
-        self.buf.is_empty()
+        if lfn.seq_number & 0x40 != 0 {
+            // The entry holding the end of the name
+            // always starts a new sequence
+            self.clear();
+            if seq != 0 {
+                self.checksum = lfn.checksum;
+                self.start = offset;
+                self.buf.push((seq, name_buf));
+            }
+            return;
+        }
+
+        let continues = match self.buf.last() {
+            Some(&(prev, _)) => prev == seq + 1 && lfn.checksum == self.checksum,
+            None => false
+        };
+        if continues {
+            self.buf.push((seq, name_buf));
+        } else {
+            self.clear();
+        }
     }
 
     fn clear(&mut self) {
         self.buf.clear();
     }
 
-    // Re-order everything recorded in this LFN sequence
-    // and decode them into string.
-    // Then clear everything.
-    fn decode(&mut self) -> String {
-        self.buf.sort_by(|&(seq1, _), &(seq2, _)| seq1.cmp(&seq2));
+    // Decode the recorded LFN sequence into a string if it
+    // is complete and belongs to the entry with the short name
+    // `short`. Then clear everything.
+    fn decode(&mut self, short: &[u8; 11]) -> Option<String> {
+        let complete = match self.buf.last() {
+            Some(&(seq, _)) => seq == 1 && self.checksum == name::lfn_checksum(short),
+            None => false
+        };
+        if !complete {
+            self.clear();
+            return None;
+        }
+
+        // Sequences are stored in reverse order
         let ret = decode_file_name_utf16(&self.buf
             .iter()
+            .rev()
             .flat_map(|&(_, ref x)| x)
             .map(|x| *x)
             .collect::<Vec<_>>()[..]).trim().to_string();
         self.clear();
-        ret
+        Some(ret)
     }
 }
 
 pub struct DirIter {
     drive: Shared<VFat>,
+    cluster: Cluster, // First cluster of the directory being iterated
     buf: Vec<u8>,
     long_file_name: LfnList,
+    code_page: CodePage, // Code page of the 8.3 names
     pos: usize
 }
 
 impl DirIter {
     fn parse_regular_dir(&mut self, dir: VFatRegularDirEntry) -> Entry {
-        let mut name;
-        if !self.long_file_name.is_empty() {
-            // A regular entry can be preceeded by
-            // as many LFNs as needed to contain the
-            // entire file name.
-            // If there is any LFN before this file, we should
-            // decode it before continuing.
-            name = self.long_file_name.decode();
-        } else {
-            name = decode_file_name_utf8_ascii(&dir.name);
-            if dir.extension[0] != 0x00 && dir.extension[0] != 0x20 {
-                name = format!("{}.{}", name, decode_file_name_utf8_ascii(&dir.extension));
-            }
-        }
-        
-        let cluster = Cluster::from(((dir.first_cluster_high as u32) << 16) + dir.first_cluster_low as u32);
-        let metadata = Metadata {
-            is_read_only: dir.attribute.has_flag(Attributes::READ_ONLY),
-            is_hidden: dir.attribute.has_flag(Attributes::HIDDEN),
-            created: Timestamp {
-                date: dir.create_date,
-                time: dir.create_time
-            },
-            last_accessed: Timestamp {
-                date: dir.last_access_date,
-                time: Time::empty()
-            },
-            last_modified: Timestamp {
-                date: dir.last_modification_date,
-                time: dir.last_modification_time
+        let mut location = EntryLocation {
+            dir: self.cluster,
+            start: self.long_file_name.start,
+            offset: self.pos - 32
+        };
+
+        // A regular entry can be preceeded by
+        // as many LFNs as needed to contain the
+        // entire file name.
+        // If there is any valid LFN before this file, we should
+        // decode it before continuing.
+        let long_name = self.long_file_name.decode(&dir.short_name());
+        let name = match long_name {
+            Some(ref name) => name.clone(),
+            None => {
+                // LFN entries that don't belong to this entry
+                // are not deleted along with it
+                location.start = location.offset;
+                dir.decode_short_name(&self.code_page, true)
             }
         };
+        let short_name = dir.decode_short_name(&self.code_page, false);
+        
+        let cluster = dir.cluster();
+        let metadata = dir.metadata();
         if dir.attribute.has_flag(Attributes::DIRECTORY) {
             // Is a directory!
             Entry::Dir(Dir {
                 drive: self.drive.clone(),
                 cluster,
                 name,
-                metadata
+                short_name,
+                long_name,
+                metadata,
+                entry: Some(location)
             })
         } else {
             // Is a file!
@@ -253,9 +861,13 @@ impl DirIter {
                 drive: self.drive.clone(),
                 cluster,
                 name,
+                short_name,
+                long_name,
                 metadata,
                 size: dir.size as u64,
-                offset: 0
+                offset: 0,
+                entry: location,
+                dirty: false
             })
         }
     }
@@ -265,7 +877,7 @@ impl Iterator for DirIter {
     type Item = Entry;
 
     fn next(&mut self) -> Option<Entry> {
-        if (self.pos + 32) >= self.buf.len() {
+        if (self.pos + 32) > self.buf.len() {
             // We must have exhausted the cluster chain
             return None;
         }
@@ -283,7 +895,7 @@ impl Iterator for DirIter {
                 // A LFN entry will preceed any future regular file entries
                 // we need to record them and reorder them when we
                 // have reached the regular entry
-                self.long_file_name.push(lfn);
+                self.long_file_name.push(lfn, self.pos - 32);
                 self.next() // Tail-recursive loop :)
             },
             VFatDirEntrySafe::End => None,
@@ -303,10 +915,13 @@ impl traits::Dir for Dir {
     fn entries(&self) -> io::Result<DirIter> {
         let mut buf: Vec<u8> = Vec::new();
         self.drive.read_chain(self.cluster, &mut buf)?;
+        let code_page = self.drive.borrow().code_page();
         Ok(DirIter {
             drive: self.drive.clone(),
+            cluster: self.cluster,
             buf,
             long_file_name: LfnList::new(),
+            code_page,
             pos: 0
         })
     }
@@ -21,10 +21,10 @@ pub struct BiosParameterBlock {
     pub hidden_sectors: u32, // Number of hidden sectors
     logical_sectors_4: u32,
     sector_per_fat_4: u32,
-    _flags: u16,
+    flags: u16, // Bits 0-3: active FAT, bit 7: FAT mirroring disabled
     _fat_ver: u16, // The high byte is the major version and the low byte is the minor version.
     pub root_cluster: u32, // The cluster number of the root directory. Often this field is set to 2.
-    _fsinfo_sector: u16, // The sector number of the FSInfo structure.
+    pub fsinfo_sector: u16, // The sector number of the FSInfo structure.
     _backup_boot_sector: u16, // The sector number of the backup boot sector.
     _reserved: [u8; 12], // Reserved. When the volume is formated these bytes should be zero.
     _drive_number: u8, // 0x00 for a floppy disk and 0x80 for hard disks.
@@ -59,6 +59,24 @@ impl BiosParameterBlock {
         }
     }
 
+    pub fn get_total_sectors(&self) -> u32 {
+        if self.logical_sectors_2 != 0 {
+            self.logical_sectors_2 as u32
+        } else {
+            self.logical_sectors_4
+        }
+    }
+
+    /// Returns the zero-based index of the only FAT in use if FAT mirroring is
+    /// disabled, or `None` if every FAT is a mirror of the first one.
+    pub fn get_active_fat(&self) -> Option<u8> {
+        if self.flags & 0x80 != 0 {
+            Some((self.flags & 0x0F) as u8)
+        } else {
+            None
+        }
+    }
+
     pub fn get_sector_per_fat(&self) -> u32 {
         if self.sector_per_fat_2 != 0 {
             self.sector_per_fat_2 as u32
@@ -66,6 +84,23 @@ impl BiosParameterBlock {
             self.sector_per_fat_4
         }
     }
+
+    /// Whether the fields of `self` describe a plausible FAT32 volume. Used
+    /// to tell a volume boot record from a master boot record, as both carry
+    /// the 0xAA55 signature.
+    pub fn is_fat32(&self) -> bool {
+        let bytes_per_sector = self.bytes_per_sector;
+        (self.bootstrap[0] == 0xEB || self.bootstrap[0] == 0xE9)
+            && bytes_per_sector.is_power_of_two()
+            && bytes_per_sector >= 512 && bytes_per_sector <= 4096
+            && self.sectors_per_cluster.is_power_of_two()
+            && self.reserved_sectors != 0
+            && self.fat_num != 0
+            && self.sector_per_fat_2 == 0
+            && self.sector_per_fat_4 != 0
+            && self.root_cluster >= 2
+            && self.bootable_signature == 0xAA55
+    }
 }
 
 impl fmt::Debug for BiosParameterBlock {
@@ -1,5 +1,7 @@
+use std::io;
+
 use traits;
-use vfat::{File, Dir, Metadata};
+use vfat::{File, Dir, Metadata, Attributes, EntryLocation};
 
 // TODO: You may need to change this definition.
 #[derive(Debug)]
@@ -10,6 +12,51 @@ pub enum Entry {
 
 // TODO: Implement any useful helper methods on `Entry`.
 
+impl Entry {
+    /// The 8.3 alias of the name of `self`.
+    pub fn short_name(&self) -> &str {
+        match *self {
+            Entry::File(ref file) => file.short_name(),
+            Entry::Dir(ref dir) => dir.short_name()
+        }
+    }
+
+    /// The name stored in LFN entries, or `None` if `self` only has an 8.3
+    /// name.
+    pub fn long_name(&self) -> Option<&str> {
+        match *self {
+            Entry::File(ref file) => file.long_name(),
+            Entry::Dir(ref dir) => dir.long_name()
+        }
+    }
+
+    /// The attribute byte of the directory entry of `self`.
+    pub fn attributes(&self) -> u8 {
+        match *self {
+            Entry::File(ref file) => file.attributes(),
+            Entry::Dir(ref dir) => dir.attributes()
+        }
+    }
+
+    /// Changes the attributes of `self` on disk, see
+    /// `EntryLocation::set_attributes()`.
+    pub fn set_attributes(&mut self, attributes: Attributes) -> io::Result<()> {
+        match *self {
+            Entry::File(ref mut file) => file.set_attributes(attributes),
+            Entry::Dir(ref mut dir) => dir.set_attributes(attributes)
+        }
+    }
+
+    /// Where the directory entry of `self` lives, or `None` for the root
+    /// directory.
+    pub fn location(&self) -> Option<EntryLocation> {
+        match *self {
+            Entry::File(ref file) => Some(file.entry),
+            Entry::Dir(ref dir) => dir.entry
+        }
+    }
+}
+
 impl traits::Entry for Entry {
     type File = File;
     type Dir = Dir;
@@ -1,7 +1,7 @@
 use std::fmt;
 use vfat::*;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Status {
     /// The FAT entry corresponds to an unused (free) cluster.
     Free,
@@ -17,6 +17,21 @@ pub enum Status {
     Eoc(u32)
 }
 
+impl Status {
+    /// Returns the value (without the reserved high 4 bits) representing
+    /// this status in a FAT entry.
+    pub fn raw(&self) -> u32 {
+        match *self {
+            Status::Free => 0x00000000,
+            Status::Reserved => 0x00000001,
+            Status::Data(cluster) => cluster.get(),
+            Status::Bad => 0x0FFFFFF7,
+            Status::Eoc(value) if value >= 0x0FFFFFF8 && value <= 0x0FFFFFFF => value,
+            Status::Eoc(_) => 0x0FFFFFFF
+        }
+    }
+}
+
 #[repr(C, packed)]
 pub struct FatEntry(pub u32);
 
@@ -36,6 +51,12 @@ impl FatEntry {
             Status::Eoc(entry_value)
         }
     }
+
+    /// Changes the entry to `status`. The reserved high 4 bits of the entry
+    /// are preserved.
+    pub fn set_status(&mut self, status: Status) {
+        self.0 = (self.0 & 0xF0000000) | (status.raw() & 0x0FFFFFFF);
+    }
 }
 
 impl fmt::Debug for FatEntry {
@@ -2,19 +2,46 @@ use std::cmp::{min, max};
 use std::io::{self, SeekFrom};
 
 use traits;
-use vfat::{VFat, VFatExt, Shared, Cluster, Metadata};
+use vfat::{VFat, VFatExt, Shared, Cluster, Metadata, Attributes, EntryLocation};
 
 #[derive(Debug)]
 pub struct File {
     pub drive: Shared<VFat>,
     pub cluster: Cluster,
     pub name: String,
+    pub short_name: String, // The 8.3 alias of `name`
+    pub long_name: Option<String>, // The name stored in LFN entries, if any
     pub metadata: Metadata,
     pub size: u64,
-    pub offset: u64
+    pub offset: u64,
+    pub entry: EntryLocation, // Where the directory entry of this file lives
+    pub dirty: bool // Whether the file was written to since the last flush
 }
 
 impl File {
+    /// The 8.3 alias of the name of `self`.
+    pub fn short_name(&self) -> &str {
+        &self.short_name
+    }
+
+    /// The name stored in LFN entries, or `None` if `self` only has an 8.3
+    /// name.
+    pub fn long_name(&self) -> Option<&str> {
+        self.long_name.as_ref().map(|name| name.as_str())
+    }
+
+    /// The attribute byte of the directory entry of `self`.
+    pub fn attributes(&self) -> u8 {
+        self.metadata.attributes.raw()
+    }
+
+    /// Changes the attributes of `self` on disk, see
+    /// `EntryLocation::set_attributes()`.
+    pub fn set_attributes(&mut self, attributes: Attributes) -> io::Result<()> {
+        self.metadata.attributes = self.entry.set_attributes(&self.drive, attributes)?;
+        Ok(())
+    }
+
     fn set_offset(&mut self, pos: u64) -> io::Result<u64> {
         if pos > self.size {
             Err(io::Error::new(io::ErrorKind::InvalidInput, "Cannot seek beyond file end"))
@@ -27,7 +54,8 @@ impl File {
 
 impl traits::File for File {
     fn sync(&mut self) -> io::Result<()> {
-        unimplemented!();
+        use std::io::Write;
+        self.flush()
     }
 
     fn size(&self) -> u64 {
@@ -55,7 +83,7 @@ impl io::Seek for File {
         match pos {
             SeekFrom::Start(p) => self.set_offset(p),
             SeekFrom::Current(p) => self.set_offset(((cur_offset as i64) + p) as u64),
-            SeekFrom::End(p) => self.set_offset(((cur_size as i64) - 1 + p) as u64)
+            SeekFrom::End(p) => self.set_offset(((cur_size as i64) + p) as u64)
         }
     }
 }
@@ -71,12 +99,55 @@ impl io::Read for File {
 }
 
 impl io::Write for File {
+    /// Writes `buf` at the current offset, overwriting existing data and
+    /// growing the file (and its cluster chain) as needed.
+    ///
+    /// The file is marked for archiving by setting its `ARCHIVE` attribute.
+    /// The new size, first cluster, attributes and modification time only
+    /// reach the directory entry of the file on `flush()` or `sync()`.
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        unimplemented!();
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // FAT32 stores file sizes in 32 bits
+        let max_len = min(buf.len() as u64, (u32::max_value() as u64).saturating_sub(self.offset)) as usize;
+        if max_len == 0 {
+            return Err(io::Error::new(io::ErrorKind::Other, "Maximum file size reached"));
+        }
+
+        // Empty files don't own any cluster yet
+        if self.cluster.get() == 0 {
+            self.cluster = self.drive.borrow_mut().alloc_cluster(None)?;
+            self.dirty = true;
+        }
+
+        self.metadata.attributes.set_flag(Attributes::ARCHIVE, true);
+
+        let written = self.drive.write_cluster(self.cluster, self.offset as usize, &buf[..max_len])?;
+        self.offset += written as u64;
+        if self.offset > self.size {
+            self.size = self.offset;
+        }
+        // The modification time changes even if the size doesn't
+        self.dirty = true;
+        Ok(written)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        unimplemented!();
+        if self.dirty {
+            let mut entry = self.entry.read(&self.drive)?;
+            entry.set_cluster(self.cluster);
+            entry.set_size(self.size as u32);
+            entry.set_attribute(self.metadata.attributes);
+            if let Some(now) = self.drive.borrow().now() {
+                entry.set_modified(&now);
+            }
+            self.metadata = entry.metadata();
+            self.entry.write(&self.drive, &entry)?;
+            self.dirty = false;
+        }
+        self.drive.borrow_mut().flush()
     }
 }
 
@@ -0,0 +1,34 @@
+use std::fmt;
+
+/// The FAT32 FSInfo sector, holding hints about free clusters.
+#[repr(C, packed)]
+pub struct FsInfo {
+    lead_signature: u32, // Should be 0x41615252 ("RRaA")
+    _reserved: [u8; 480],
+    struct_signature: u32, // Should be 0x61417272 ("rrAa")
+    pub free_count: u32, // Last known free cluster count, 0xFFFFFFFF if unknown
+    pub next_free: u32, // Cluster to start looking for free clusters from, 0xFFFFFFFF if unknown
+    _reserved_2: [u8; 12],
+    trail_signature: u32 // Should be 0xAA550000
+}
+
+impl FsInfo {
+    /// Value of `free_count` and `next_free` when the field is not set.
+    pub const UNKNOWN: u32 = 0xFFFFFFFF;
+
+    /// Whether all three signatures of the sector are valid.
+    pub fn is_valid(&self) -> bool {
+        self.lead_signature == 0x41615252
+            && self.struct_signature == 0x61417272
+            && self.trail_signature == 0xAA550000
+    }
+}
+
+impl fmt::Debug for FsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FsInfo")
+            .field("free_count", &{ self.free_count })
+            .field("next_free", &{ self.next_free })
+            .finish()
+    }
+}
@@ -1,4 +1,8 @@
 use std::fmt;
+#[cfg(not(target_os = "ros"))]
+use std::cmp::max;
+#[cfg(not(target_os = "ros"))]
+use std::time::{SystemTime, Duration, UNIX_EPOCH};
 
 use traits;
 
@@ -11,6 +15,12 @@ impl Date {
     pub fn empty() -> Date {
         Date(0)
     }
+
+    /// Creates the date `year`-`month`-`day`. `year` must be in the range
+    /// [1980, 2107], `month` in [1, 12] and `day` in [1, 31].
+    pub fn new(year: usize, month: u8, day: u8) -> Date {
+        Date((((year - 1980) as u16) << 9) | ((month as u16) << 5) | day as u16)
+    }
 }
 
 /// Time as represented in FAT32 on-disk structures.
@@ -22,6 +32,12 @@ impl Time {
     pub fn empty() -> Time {
         Time(0)
     }
+
+    /// Creates the time `hour`:`minute`:`second`. `second` is rounded down to
+    /// an even number, the resolution of FAT times.
+    pub fn new(hour: u8, minute: u8, second: u8) -> Time {
+        Time(((hour as u16) << 11) | ((minute as u16) << 5) | (second as u16 >> 1))
+    }
 }
 
 /// File attributes as represented in FAT32 on-disk structures.
@@ -37,42 +53,236 @@ impl Attributes {
     pub const DIRECTORY: u8 = 0x10;
     pub const ARCHIVE: u8 = 0x20;
     pub const LFN: u8 = 0x0F;
+    /// Attributes that can be changed on an existing entry.
+    pub const MUTABLE: u8 = Self::READ_ONLY | Self::HIDDEN | Self::SYSTEM | Self::ARCHIVE;
 
     pub fn has_flag(&self, flag: u8) -> bool {
         self.0 & flag != 0
     }
 
+    /// Sets the bits of `flag` if `value` is `true`, clears them otherwise.
+    pub fn set_flag(&mut self, flag: u8, value: bool) {
+        if value {
+            self.0 |= flag;
+        } else {
+            self.0 &= !flag;
+        }
+    }
+
     pub fn equal_to(&self, flag: u8) -> bool {
         self.0 == flag
     }
+
+    /// The attribute byte as stored on disk.
+    pub fn raw(&self) -> u8 {
+        self.0
+    }
 }
 
-/// A structure containing a date and time.
-#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+impl From<u8> for Attributes {
+    fn from(flags: u8) -> Attributes {
+        Attributes(flags)
+    }
+}
+
+/// A structure containing a date and, unless it is a date-only timestamp, a
+/// time.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Timestamp {
     pub date: Date,
-    pub time: Time
+    pub time: Option<Time>, // `None` for date-only timestamps (last access)
+    pub centiseconds: u8 // 10 ms units (0-199) on top of `time`, creation only
 }
 
 impl Timestamp {
     pub fn empty() -> Timestamp {
+        Timestamp::new(Date::empty(), Time::empty())
+    }
+
+    /// Creates a full timestamp with a 2 second resolution.
+    pub fn new(date: Date, time: Time) -> Timestamp {
+        Timestamp {
+            date,
+            time: Some(time),
+            centiseconds: 0
+        }
+    }
+
+    /// Creates a timestamp that only records the day.
+    pub fn date_only(date: Date) -> Timestamp {
+        Timestamp {
+            date,
+            time: None,
+            centiseconds: 0
+        }
+    }
+
+    /// Whether `self` only records the day, in which case the hour, minute,
+    /// second and millisecond are all 0.
+    pub fn is_date_only(&self) -> bool {
+        self.time.is_none()
+    }
+
+    // The raw on-disk time, 0 for date-only timestamps
+    fn raw_time(&self) -> u16 {
+        self.time.map(|time| time.0).unwrap_or(0)
+    }
+
+    // The centiseconds, ignored if out of range
+    fn raw_centiseconds(&self) -> u8 {
+        if self.centiseconds < 200 { self.centiseconds } else { 0 }
+    }
+}
+
+#[cfg(not(target_os = "ros"))]
+impl Timestamp {
+    /// Converts `self`, a local time `utc_offset` seconds east of UTC, into a
+    /// `SystemTime`. Invalid months and days (as in empty timestamps) are
+    /// treated as 1.
+    pub fn to_system_time(&self, utc_offset: i32) -> SystemTime {
+        use traits::Timestamp;
+
+        let days = days_from_civil(self.year() as i64, max(self.month(), 1), max(self.day(), 1));
+        let local = days * SECONDS_PER_DAY
+            + self.hour() as i64 * 3600
+            + self.minute() as i64 * 60
+            + self.second() as i64;
+        let utc = local - utc_offset as i64;
+
+        let nanos = self.millisecond() as u32 * 1_000_000;
+        if utc >= 0 {
+            UNIX_EPOCH + Duration::new(utc as u64, nanos)
+        } else {
+            UNIX_EPOCH - Duration::new((-utc) as u64, 0) + Duration::new(0, nanos)
+        }
+    }
+
+    /// Converts `time` into a timestamp in the local time `utc_offset`
+    /// seconds east of UTC.
+    ///
+    /// Times outside of the range FAT can represent, 1980-01-01 00:00:00 to
+    /// 2107-12-31 23:59:59, are clamped to it. The time is kept with a 10 ms
+    /// resolution in `centiseconds`; fields that don't store centiseconds
+    /// round it down to 2 seconds.
+    pub fn from_system_time(time: SystemTime, utc_offset: i32) -> Timestamp {
+        let (secs, nanos) = match time.duration_since(UNIX_EPOCH) {
+            Ok(duration) => (duration.as_secs() as i64, duration.subsec_nanos()),
+            Err(_) => (i64::min_value() / 2, 0)
+        };
+
+        let min = days_from_civil(1980, 1, 1) * SECONDS_PER_DAY;
+        let max = days_from_civil(2107, 12, 31) * SECONDS_PER_DAY + SECONDS_PER_DAY - 1;
+        let local = secs.saturating_add(utc_offset as i64);
+        let (local, nanos) = if local < min {
+            (min, 0)
+        } else if local > max {
+            (max, 0)
+        } else {
+            (local, nanos)
+        };
+
+        let (year, month, day) = civil_from_days(local / SECONDS_PER_DAY);
+        let seconds = local % SECONDS_PER_DAY;
+        let second = (seconds % 60) as u8;
         Timestamp {
-            date: Date::empty(),
-            time: Time::empty()
+            date: Date::new(year as usize, month, day),
+            time: Some(Time::new((seconds / 3600) as u8, (seconds / 60 % 60) as u8, second)),
+            centiseconds: (second % 2) * 100 + (nanos / 10_000_000) as u8
         }
     }
 }
 
+/// Converts UTC times, see `Timestamp::from_system_time()`.
+#[cfg(not(target_os = "ros"))]
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Timestamp {
+        Timestamp::from_system_time(time, 0)
+    }
+}
+
+/// Converts timestamps in UTC, see `Timestamp::to_system_time()`.
+#[cfg(not(target_os = "ros"))]
+impl From<Timestamp> for SystemTime {
+    fn from(timestamp: Timestamp) -> SystemTime {
+        timestamp.to_system_time(0)
+    }
+}
+
+#[cfg(not(target_os = "ros"))]
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+// Number of days between 1970-01-01 and the given date
+// of the proleptic Gregorian calendar
+// See http://howardhinnant.github.io/date_algorithms.html
+#[cfg(not(target_os = "ros"))]
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// Inverse of `days_from_civil`: (year, month, day)
+#[cfg(not(target_os = "ros"))]
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719468;
+    let era = (if days >= 0 { days } else { days - 146096 }) / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u8;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl Default for Timestamp {
+    fn default() -> Timestamp {
+        Timestamp::empty()
+    }
+}
+
 /// Metadata for a directory entry.
 #[derive(Default, Debug, Clone)]
 pub struct Metadata {
-    pub is_read_only: bool,
-    pub is_hidden: bool,
+    pub attributes: Attributes,
     pub created: Timestamp,
     pub last_accessed: Timestamp,
     pub last_modified: Timestamp
 }
 
+impl Metadata {
+    pub fn is_read_only(&self) -> bool {
+        self.attributes.has_flag(Attributes::READ_ONLY)
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.attributes.has_flag(Attributes::HIDDEN)
+    }
+
+    pub fn is_system(&self) -> bool {
+        self.attributes.has_flag(Attributes::SYSTEM)
+    }
+
+    pub fn is_volume_id(&self) -> bool {
+        self.attributes.has_flag(Attributes::VOLUME_ID)
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attributes.has_flag(Attributes::DIRECTORY)
+    }
+
+    /// Whether the entry was modified since the archive bit was last cleared
+    /// (typically by a backup tool).
+    pub fn is_archive(&self) -> bool {
+        self.attributes.has_flag(Attributes::ARCHIVE)
+    }
+}
+
 impl traits::Timestamp for Timestamp {
     fn year(&self) -> usize {
         (self.date.0 >> 9) as usize + 1980
@@ -87,15 +297,21 @@ impl traits::Timestamp for Timestamp {
     }
 
     fn hour(&self) -> u8 {
-        (self.time.0 >> 11) as u8
+        (self.raw_time() >> 11) as u8
     }
 
     fn minute(&self) -> u8 {
-        ((self.time.0 & 0b0000011111100000) >> 5) as u8
+        ((self.raw_time() & 0b0000011111100000) >> 5) as u8
     }
 
     fn second(&self) -> u8 {
-        ((self.time.0 & 0b0000000000011111) << 1) as u8
+        // The time only has a 2 second resolution, odd
+        // seconds are stored along with the centiseconds
+        ((self.raw_time() & 0b0000000000011111) << 1) as u8 + self.raw_centiseconds() / 100
+    }
+
+    fn millisecond(&self) -> u16 {
+        (self.raw_centiseconds() % 100) as u16 * 10
     }
 }
 
@@ -103,11 +319,11 @@ impl traits::Metadata for Metadata {
     type Timestamp = Timestamp;
 
     fn read_only(&self) -> bool {
-        self.is_read_only
+        self.is_read_only()
     }
 
     fn hidden(&self) -> bool {
-        self.is_hidden
+        self.is_hidden()
     }
 
     fn created(&self) -> Timestamp {
@@ -2,22 +2,30 @@ pub(crate) mod file;
 pub(crate) mod dir;
 pub(crate) mod vfat;
 pub(crate) mod ebpb;
+pub(crate) mod fsinfo;
 pub(crate) mod error;
 pub(crate) mod cluster;
 pub(crate) mod fat;
 pub(crate) mod entry;
 pub(crate) mod metadata;
+pub(crate) mod name;
+pub(crate) mod codepage;
 pub(crate) mod cache;
+pub(crate) mod policy;
 pub(crate) mod shared;
 
 pub use self::ebpb::BiosParameterBlock;
+pub use self::fsinfo::FsInfo;
 pub use self::file::File;
-pub use self::dir::Dir;
+pub use self::dir::{Dir, EntryLocation};
 pub use self::error::Error;
 pub use self::vfat::{VFat, VFatExt};
 pub use self::entry::Entry;
 pub use self::metadata::{Metadata, Attributes, Date, Time, Timestamp};
 pub use self::shared::Shared;
+pub use self::cache::CacheStats;
+pub use self::policy::{CachePolicy, Lru, Clock, PinMetadata};
+pub use self::codepage::CodePage;
 
 pub(crate) use self::cache::{CachedDevice, Partition};
 pub(crate) use self::fat::{Status, FatEntry};
@@ -0,0 +1,244 @@
+// Helpers to turn user supplied names into on-disk 8.3 and LFN names
+
+use vfat::CodePage;
+
+// Characters that are never allowed in a long file name
+const INVALID_LONG_CHARS: &'static str = "\"*/:<>?\\|";
+
+// Characters other than letters and digits allowed in a short name
+const VALID_SHORT_SPECIAL_CHARS: &'static str = "!#$%&'()-@^_`{}~";
+
+// Lower case characters whose simple case folding is another character,
+// sorted. Every other character folds to its lower case version.
+const FOLDING_EXCEPTIONS: &'static [(char, char)] = &[
+    ('\u{00B5}', '\u{03BC}'), // micro sign
+    ('\u{017F}', 's'), // long s
+    ('\u{0345}', '\u{03B9}'), // combining ypogegrammeni
+    ('\u{03C2}', '\u{03C3}'), // final sigma
+    ('\u{03D0}', '\u{03B2}'), // beta symbol
+    ('\u{03D1}', '\u{03B8}'), // theta symbol
+    ('\u{03D5}', '\u{03C6}'), // phi symbol
+    ('\u{03D6}', '\u{03C0}'), // pi symbol
+    ('\u{03F0}', '\u{03BA}'), // kappa symbol
+    ('\u{03F1}', '\u{03C1}'), // rho symbol
+    ('\u{03F5}', '\u{03B5}'), // lunate epsilon symbol
+    ('\u{1C80}', '\u{0432}'), // rounded ve
+    ('\u{1C81}', '\u{0434}'), // long-legged de
+    ('\u{1C82}', '\u{043E}'), // narrow o
+    ('\u{1C83}', '\u{0441}'), // wide es
+    ('\u{1C84}', '\u{0442}'), // tall te
+    ('\u{1C85}', '\u{0442}'), // three-legged te
+    ('\u{1C86}', '\u{044A}'), // tall hard sign
+    ('\u{1C87}', '\u{0463}'), // tall yat
+    ('\u{1C88}', '\u{A64B}'), // unblended uk
+    ('\u{1E9B}', '\u{1E61}'), // long s with dot above
+    ('\u{1FBE}', '\u{03B9}') // prosgegrammeni
+];
+
+// Simple case folding of `c` (the C and S mappings of CaseFolding.txt):
+// its lower case version if that is a single character,
+// mapped through `FOLDING_EXCEPTIONS`
+fn fold_case(c: char) -> char {
+    let mut lower = c.to_lowercase();
+    let lower = match (lower.next(), lower.next()) {
+        (Some(l), None) => l,
+        _ => c
+    };
+
+    match FOLDING_EXCEPTIONS.binary_search_by_key(&lower, |&(from, _)| from) {
+        Ok(index) => FOLDING_EXCEPTIONS[index].1,
+        Err(_) => lower
+    }
+}
+
+/// Compares `a` and `b` case-insensitively, using Unicode simple case
+/// folding. Unlike full case folding, `ß` doesn't match `ss`.
+pub fn eq_ignore_case(a: &str, b: &str) -> bool {
+    a.chars().map(fold_case).eq(b.chars().map(fold_case))
+}
+
+/// Flag in the NT case byte of a directory entry marking the base of the 8.3
+/// name as lower case.
+pub const LOWERCASE_BASE: u8 = 0x08;
+
+/// Flag in the NT case byte of a directory entry marking the extension of the
+/// 8.3 name as lower case.
+pub const LOWERCASE_EXTENSION: u8 = 0x10;
+
+/// Checks whether `name` may be used as the (long) name of a new entry.
+///
+/// Names are rejected when they are empty, `.` or `..`, longer than 255
+/// UTF-16 code units, start or end with whitespace, end with a period, or
+/// contain control characters or any of `"*/:<>?\|`.
+pub fn is_valid_long_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.encode_utf16().count() <= 255
+        && name.trim() == name
+        && !name.ends_with('.')
+        && !name.chars().any(|c| (c as u32) < 0x20 || INVALID_LONG_CHARS.contains(c))
+}
+
+// Upper case version of `c`, if it is a single character
+fn to_upper(c: char) -> char {
+    let mut upper = c.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(u), None) => u,
+        _ => c
+    }
+}
+
+// Convert the characters of `part` to what can be stored in a short name:
+// upper case letters, digits, some special characters and
+// characters from the upper half of `code_page`.
+// Spaces and periods are dropped and every other character
+// is replaced by `_`. Returns the converted characters and whether
+// any of them had to be replaced.
+fn short_name_part(part: &str, code_page: &CodePage) -> (Vec<u8>, bool) {
+    let mut lossy = false;
+    let bytes = part.chars()
+        .filter(|&c| c != ' ' && c != '.')
+        .map(|c| if c.is_ascii_alphanumeric() || VALID_SHORT_SPECIAL_CHARS.contains(c) {
+            c.to_ascii_uppercase() as u8
+        } else {
+            match code_page.encode_char(to_upper(c)) {
+                Some(byte) if byte >= 0x80 => byte,
+                _ => {
+                    lossy = true;
+                    b'_'
+                }
+            }
+        })
+        .collect();
+    (bytes, lossy)
+}
+
+/// Generates an 8.3 short name (8 bytes of name followed by 3 bytes of
+/// extension, padded with spaces) for `name` that is different from every
+/// name in `existing`, following the basis-name algorithm of Windows.
+///
+/// Letters are upper-cased, spaces and leading periods are stripped, and
+/// characters not allowed in short names or missing from `code_page` are
+/// replaced by `_`. The extension
+/// is made of the first 3 characters after the last period. If `name` is
+/// already a valid 8.3 name, it is used as is as long as it is unique.
+/// Otherwise, the basis name is truncated and followed by a numeric tail
+/// `~N`, with `N` counting up from 1 until the name is unique.
+pub fn short_name(name: &str, existing: &[[u8; 11]], code_page: &CodePage) -> [u8; 11] {
+    // The extension starts after the last period,
+    // leading periods are not taken into account
+    let trimmed = name.trim_left_matches('.');
+    let (base, extension) = match trimmed.rfind('.') {
+        Some(index) => (&trimmed[..index], &trimmed[(index + 1)..]),
+        None => (trimmed, "")
+    };
+    let (base, base_lossy) = short_name_part(base, code_page);
+    let (extension, extension_lossy) = short_name_part(extension, code_page);
+
+    let mut short = [b' '; 11];
+    let extension_len = ::std::cmp::min(extension.len(), 3);
+    short[8..(8 + extension_len)].copy_from_slice(&extension[..extension_len]);
+
+    // Names that fit without losing anything need no numeric tail
+    let fits = !base_lossy && !extension_lossy
+        && !base.is_empty() && base.len() <= 8 && extension.len() <= 3
+        && !name.starts_with('.') && !name.contains(' ') && name.matches('.').count() <= 1;
+    if fits {
+        short[..base.len()].copy_from_slice(&base);
+        escape_lead_byte(&mut short);
+        if !existing.contains(&short) {
+            return short;
+        }
+    }
+
+    let mut n = 1;
+    loop {
+        let tail = format!("~{}", n);
+        let keep = ::std::cmp::min(base.len(), 8 - tail.len());
+        for byte in short[..8].iter_mut() {
+            *byte = b' ';
+        }
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..(keep + tail.len())].copy_from_slice(tail.as_bytes());
+        escape_lead_byte(&mut short);
+
+        if !existing.contains(&short) {
+            return short;
+        }
+        n += 1;
+    }
+}
+
+// 0xE5 as the first byte marks deleted entries,
+// names starting with it store 0x05 instead
+fn escape_lead_byte(short: &mut [u8; 11]) {
+    if short[0] == 0xE5 {
+        short[0] = 0x05;
+    }
+}
+
+// Whether the letters in `part` are all upper case (`Some(false)`)
+// or all lower case (`Some(true)`)
+fn uniform_case(part: &str) -> Option<bool> {
+    let has_lower = part.chars().any(|c| c.is_ascii_lowercase());
+    let has_upper = part.chars().any(|c| c.is_ascii_uppercase());
+    if has_lower && has_upper {
+        None
+    } else {
+        Some(has_lower)
+    }
+}
+
+/// Returns the NT case flags to store along with the 8.3 name `short` if
+/// together they represent `name` exactly, so that no LFN entries are needed.
+/// Returns `None` if `name` doesn't fit `short` or mixes cases inside of its
+/// base name or extension.
+pub fn case_flags(name: &str, short: &[u8; 11], code_page: &CodePage) -> Option<u8> {
+    let base = code_page.decode(&short[..8]);
+    let extension = code_page.decode(&short[8..]);
+    let decoded = if extension.is_empty() {
+        base.clone()
+    } else {
+        format!("{}.{}", base, extension)
+    };
+    if !decoded.eq_ignore_ascii_case(name) {
+        return None;
+    }
+
+    let mut flags = 0;
+    if uniform_case(&name[..base.len()])? {
+        flags |= LOWERCASE_BASE;
+    }
+    if uniform_case(&name[base.len()..])? {
+        flags |= LOWERCASE_EXTENSION;
+    }
+    Some(flags)
+}
+
+/// Computes the checksum of an 8.3 short name that the LFN entries belonging
+/// to it must carry.
+pub fn lfn_checksum(short: &[u8; 11]) -> u8 {
+    short.iter().fold(0u8, |sum, &byte| {
+        ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(byte)
+    })
+}
+
+/// Splits `name` into the 13 UTF-16 code unit chunks stored in consecutive
+/// LFN entries. The name is terminated by 0x0000 if it doesn't fill the last
+/// chunk, and the remainder is padded with 0xFFFF.
+pub fn lfn_chunks(name: &str) -> Vec<[u16; 13]> {
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    if units.len() % 13 != 0 {
+        units.push(0x0000);
+    }
+    while units.len() % 13 != 0 {
+        units.push(0xFFFF);
+    }
+
+    units.chunks(13).map(|chunk| {
+        let mut buf = [0u16; 13];
+        buf.copy_from_slice(chunk);
+        buf
+    }).collect()
+}
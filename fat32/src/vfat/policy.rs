@@ -0,0 +1,195 @@
+use std::fmt;
+use std::ops::Range;
+use std::collections::{HashMap, BTreeMap};
+
+/// Trait implemented by cache replacement strategies used by the sector
+/// cache of `VFat`.
+///
+/// The cache reports every sector entering, being accessed in and leaving the
+/// cache. When the cache is full, `victim()` is asked for the sector to evict.
+pub trait CachePolicy: Send + fmt::Debug {
+    /// Records that `sector` was inserted into the cache.
+    fn inserted(&mut self, sector: u64);
+
+    /// Records an access to the cached sector `sector`.
+    fn accessed(&mut self, sector: u64);
+
+    /// Records that `sector` was removed from the cache.
+    fn removed(&mut self, sector: u64);
+
+    /// Returns the sector that should be evicted next, or `None` if no sector
+    /// is tracked by this policy. The returned sector stays tracked until
+    /// `removed()` is called for it.
+    fn victim(&mut self) -> Option<u64>;
+}
+
+/// Evicts the least recently used sector.
+#[derive(Default, Debug)]
+pub struct Lru {
+    // sector => value of `clock` at the last access
+    last_used: HashMap<u64, u64>,
+    // value of `clock` at the last access => sector, oldest first
+    by_age: BTreeMap<u64, u64>,
+    clock: u64
+}
+
+impl Lru {
+    pub fn new() -> Lru {
+        Lru::default()
+    }
+}
+
+impl CachePolicy for Lru {
+    fn inserted(&mut self, sector: u64) {
+        self.accessed(sector);
+    }
+
+    fn accessed(&mut self, sector: u64) {
+        self.clock += 1;
+        if let Some(last_used) = self.last_used.insert(sector, self.clock) {
+            self.by_age.remove(&last_used);
+        }
+        self.by_age.insert(self.clock, sector);
+    }
+
+    fn removed(&mut self, sector: u64) {
+        if let Some(last_used) = self.last_used.remove(&sector) {
+            self.by_age.remove(&last_used);
+        }
+    }
+
+    fn victim(&mut self) -> Option<u64> {
+        self.by_age.values().next().cloned()
+    }
+}
+
+/// The CLOCK (second chance) approximation of LRU.
+///
+/// Sectors sit in a ring with a "referenced" bit that is set on every access.
+/// The clock hand sweeps the ring, clearing referenced bits, and evicts the
+/// first sector whose bit is already clear.
+#[derive(Default, Debug)]
+pub struct Clock {
+    // (sector, referenced), `None` for slots of removed sectors
+    ring: Vec<Option<(u64, bool)>>,
+    // sector => index of its slot in `ring`
+    slots: HashMap<u64, usize>,
+    // Slots of removed sectors, reused by the next insertions
+    free: Vec<usize>,
+    hand: usize
+}
+
+impl Clock {
+    pub fn new() -> Clock {
+        Clock::default()
+    }
+}
+
+impl CachePolicy for Clock {
+    fn inserted(&mut self, sector: u64) {
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.ring[slot] = Some((sector, true));
+                slot
+            },
+            None => {
+                self.ring.push(Some((sector, true)));
+                self.ring.len() - 1
+            }
+        };
+        self.slots.insert(sector, slot);
+
+        // A sector taking the slot of the last victim is placed
+        // behind the hand, it is the last one the next sweep looks at
+        if slot == self.hand {
+            self.hand = (self.hand + 1) % self.ring.len();
+        }
+    }
+
+    fn accessed(&mut self, sector: u64) {
+        if let Some(&slot) = self.slots.get(&sector) {
+            if let Some(ref mut entry) = self.ring[slot] {
+                entry.1 = true;
+            }
+        }
+    }
+
+    fn removed(&mut self, sector: u64) {
+        if let Some(slot) = self.slots.remove(&sector) {
+            self.ring[slot] = None;
+            self.free.push(slot);
+        }
+    }
+
+    fn victim(&mut self) -> Option<u64> {
+        if self.slots.is_empty() {
+            return None;
+        }
+
+        // Terminates after at most two full sweeps since every
+        // referenced bit is cleared on the way
+        loop {
+            let hand = self.hand;
+            self.hand = (hand + 1) % self.ring.len();
+            if let Some(ref mut entry) = self.ring[hand] {
+                if !entry.1 {
+                    self.hand = hand;
+                    return Some(entry.0);
+                }
+                entry.1 = false;
+            }
+        }
+    }
+}
+
+/// Keeps sectors inside of `pinned` (typically the FAT region of a file
+/// system, see `VFat::fat_sectors()`) resident preferentially.
+///
+/// Sectors inside and outside of the pinned range are tracked by separate
+/// instances of the policy `P`. Pinned sectors are only evicted when no other
+/// sector is cached.
+#[derive(Debug)]
+pub struct PinMetadata<P = Lru> {
+    pinned: Range<u64>,
+    metadata: P,
+    data: P
+}
+
+impl<P: CachePolicy + Default> PinMetadata<P> {
+    pub fn new(pinned: Range<u64>) -> PinMetadata<P> {
+        PinMetadata {
+            pinned,
+            metadata: P::default(),
+            data: P::default()
+        }
+    }
+
+    fn policy_for(&mut self, sector: u64) -> &mut P {
+        if sector >= self.pinned.start && sector < self.pinned.end {
+            &mut self.metadata
+        } else {
+            &mut self.data
+        }
+    }
+}
+
+impl<P: CachePolicy + Default> CachePolicy for PinMetadata<P> {
+    fn inserted(&mut self, sector: u64) {
+        self.policy_for(sector).inserted(sector);
+    }
+
+    fn accessed(&mut self, sector: u64) {
+        self.policy_for(sector).accessed(sector);
+    }
+
+    fn removed(&mut self, sector: u64) {
+        self.policy_for(sector).removed(sector);
+    }
+
+    fn victim(&mut self) -> Option<u64> {
+        match self.data.victim() {
+            Some(sector) => Some(sector),
+            None => self.metadata.victim()
+        }
+    }
+}
@@ -1,12 +1,18 @@
 use std::io;
 use std::path::{Path, Component};
+use std::ffi::OsStr;
 use std::mem::size_of;
 use std::cmp::min;
+use std::ops::Range;
+#[cfg(not(target_os = "ros"))]
+use std::time::SystemTime;
 
 use util::SliceExt;
 use mbr::{MasterBootRecord, PartitionEntry};
+use gpt::GuidPartitionTable;
 use vfat::{Shared, Cluster, ClusterIter, File, Dir, Entry, FatEntry, Error, Status};
-use vfat::{BiosParameterBlock, CachedDevice, Partition};
+use vfat::{BiosParameterBlock, FsInfo, CachedDevice, Partition, CachePolicy, CacheStats, CodePage};
+use vfat::Timestamp;
 use traits::{FileSystem, BlockDevice};
 
 #[derive(Debug)]
@@ -15,39 +21,205 @@ pub struct VFat {
     bytes_per_sector: u16,
     sectors_per_cluster: u8,
     sectors_per_fat: u32,
+    fat_num: u8,
+    active_fat: Option<u8>, // The only FAT in use when mirroring is disabled
     fat_start_sector: u64,
     data_start_sector: u64,
+    data_clusters: u32, // Number of clusters in the data region
     root_dir_cluster: Cluster,
+    fsinfo_sector: Option<u64>, // Sector of a valid FSInfo structure, if any
+    free_count: Option<u32>, // Number of free clusters, if known
+    next_free: u32, // Cluster to start searching for free clusters from
+    code_page: CodePage, // Code page of the 8.3 names on the volume
+    utc_offset: i32 // Seconds east of UTC of the local time stored in timestamps
 }
 
 impl VFat {
+    /// Mounts the first FAT32 partition (type 0xB or 0xC) listed in the MBR
+    /// of `device`. If the MBR is a GPT protective MBR, mounts the first
+    /// Microsoft Basic Data partition of the GPT holding a FAT32 volume.
     pub fn from<T>(mut device: T) -> Result<Shared<VFat>, Error>
         where T: BlockDevice + 'static
     {
         let mbr = MasterBootRecord::from(&mut device).map_err(|e| Error::Mbr(e))?;
+        if mbr.find_partition_with_type(0xEE).is_some() {
+            let gpt = GuidPartitionTable::from(&mut device)?;
+            for (_, partition) in gpt.basic_data_partitions() {
+                let start = partition.first_lba;
+                if VFat::is_fat32_volume(&mut device, start)? {
+                    return VFat::from_volume(device, start);
+                }
+            }
+            return Err(Error::NotFound);
+        }
 
         // Find the first fat32 partition
         let fat32_part = mbr
             .find_partition_with_type(0xB)
             .or_else(|| mbr.find_partition_with_type(0xC))
             .ok_or(Error::NotFound)?;
-        let ebpb_info = BiosParameterBlock::from(&mut device, fat32_part.relative_sector as u64)?;
-        let fat_start_sector = (fat32_part.relative_sector as u64) + ebpb_info.reserved_sectors as u64;
+        VFat::from_volume(device, fat32_part.relative_sector as u64)
+    }
+
+    /// Mounts the FAT32 partition at index `index` of the partition table in
+    /// the MBR of `device`. Indices from 4 on refer to logical partitions in
+    /// the extended partition. See `MasterBootRecord::all_fat_partitions()`
+    /// to list the candidates. On GPT disks, `index` is the index of a
+    /// Microsoft Basic Data partition in the partition entry array instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if there is no partition `index` or if it is not a
+    /// FAT32 partition (or a Basic Data partition holding a FAT32 volume).
+    pub fn from_partition<T>(mut device: T, index: usize) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        let mbr = MasterBootRecord::from(&mut device).map_err(|e| Error::Mbr(e))?;
+        if mbr.find_partition_with_type(0xEE).is_some() {
+            let start = {
+                let gpt = GuidPartitionTable::from(&mut device)?;
+                match gpt.partitions.get(index) {
+                    Some(partition) if partition.is_basic_data() => partition.first_lba,
+                    _ => return Err(Error::NotFound)
+                }
+            };
+            if !VFat::is_fat32_volume(&mut device, start)? {
+                return Err(Error::NotFound);
+            }
+            return VFat::from_volume(device, start);
+        }
+
+        // Only walk the EBR chain when it is needed
+        let partitions = if index < 4 {
+            mbr.fat_partitions()
+        } else {
+            mbr.all_fat_partitions(&mut device)?
+        };
+        let partition = partitions
+            .into_iter()
+            .find(|partition| partition.index == index)
+            .ok_or(Error::NotFound)?;
+        VFat::from_volume(device, partition.start)
+    }
+
+    // Whether sector `start` of `device` is the boot sector of a FAT32
+    // volume. Basic Data partitions may hold other file systems too
+    fn is_fat32_volume<T: BlockDevice>(device: &mut T, start: u64) -> Result<bool, Error> {
+        match BiosParameterBlock::from(device, start) {
+            Ok(ebpb) => Ok(ebpb.is_fat32()),
+            Err(Error::BadSignature) => Ok(false),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Mounts the FAT32 volume whose boot sector is the sector `start` of
+    /// `device`, without looking for a partition table. `start` is 0 for
+    /// "superfloppy" images formatted without one.
+    pub fn from_volume<T>(mut device: T, start: u64) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        let ebpb_info = BiosParameterBlock::from(&mut device, start)?;
+        let fat_start_sector = start + ebpb_info.reserved_sectors as u64;
         let sector_per_fat = ebpb_info.get_sector_per_fat() as u32;
         let data_start_sector = fat_start_sector + (ebpb_info.fat_num as u64) * (sector_per_fat as u64);
+        let data_sectors = (ebpb_info.get_total_sectors() as u64)
+            .saturating_sub(data_start_sector - start);
+        let data_clusters = (data_sectors / ebpb_info.sectors_per_cluster as u64) as u32;
 
-        Ok(Shared::new(VFat {
+        let mut vfat = VFat {
             device: CachedDevice::new(device, Partition {
-                start: fat32_part.relative_sector as u64,
+                start,
                 sector_size: ebpb_info.bytes_per_sector as u64
             }),
             bytes_per_sector: ebpb_info.bytes_per_sector,
             sectors_per_cluster: ebpb_info.sectors_per_cluster,
             fat_start_sector,
             sectors_per_fat: sector_per_fat,
+            fat_num: ebpb_info.fat_num,
+            active_fat: match ebpb_info.get_active_fat() {
+                Some(fat) if fat < ebpb_info.fat_num => Some(fat),
+                _ => None
+            },
             data_start_sector,
-            root_dir_cluster: Cluster::from(ebpb_info.root_cluster)
-        }))
+            data_clusters,
+            root_dir_cluster: Cluster::from(ebpb_info.root_cluster),
+            fsinfo_sector: None,
+            free_count: None,
+            next_free: 2,
+            code_page: CodePage::default(),
+            utc_offset: 0
+        };
+
+        // 0 and 0xFFFF both mean that there is no FSInfo sector
+        if ebpb_info.fsinfo_sector != 0 && ebpb_info.fsinfo_sector != 0xFFFF {
+            vfat.load_fsinfo(start + ebpb_info.fsinfo_sector as u64)?;
+        }
+
+        Ok(Shared::new(vfat))
+    }
+
+    /// Mounts the FAT32 volume on `device`, deciding from the content of
+    /// sector 0 whether `device` holds a bare volume (see `from_volume()`) or
+    /// is partitioned with an MBR (see `from()`).
+    pub fn from_any<T>(mut device: T) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        if VFat::is_fat32_volume(&mut device, 0)? {
+            VFat::from_volume(device, 0)
+        } else {
+            VFat::from(device)
+        }
+    }
+
+    // Read the free cluster hints from the FSInfo structure in `sector`
+    // Hints out of range are ignored, as the spec requires
+    fn load_fsinfo(&mut self, sector: u64) -> io::Result<()> {
+        let (free_count, next_free) = {
+            let fsinfo = self.fsinfo(sector)?;
+            if !fsinfo.is_valid() {
+                return Ok(());
+            }
+            (fsinfo.free_count, fsinfo.next_free)
+        };
+
+        self.fsinfo_sector = Some(sector);
+        if free_count <= self.data_clusters {
+            self.free_count = Some(free_count);
+        }
+        if next_free >= 2 && next_free < self.data_clusters + 2 {
+            self.next_free = next_free;
+        }
+        Ok(())
+    }
+
+    // Return a reference to the FSInfo structure in `sector`
+    // pointing directly into the cached sector
+    fn fsinfo(&mut self, sector: u64) -> io::Result<&FsInfo> {
+        let data = self.device.get(sector)?;
+        if data.len() < size_of::<FsInfo>() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Sector too small for FSInfo"));
+        }
+        Ok(unsafe { &*(data.as_ptr() as *const FsInfo) })
+    }
+
+    // Write the in-memory free cluster hints back to FSInfo
+    // The sector is only marked dirty if a hint changed
+    fn store_fsinfo(&mut self) -> io::Result<()> {
+        if let Some(sector) = self.fsinfo_sector {
+            let free_count = self.free_count.unwrap_or(FsInfo::UNKNOWN);
+            let next_free = self.next_free;
+            {
+                let fsinfo = self.fsinfo(sector)?;
+                if fsinfo.free_count == free_count && fsinfo.next_free == next_free {
+                    return Ok(());
+                }
+            }
+
+            let fsinfo = unsafe { &mut *(self.device.get_mut(sector)?.as_mut_ptr() as *mut FsInfo) };
+            fsinfo.free_count = free_count;
+            fsinfo.next_free = next_free;
+        }
+        Ok(())
     }
 
     // Find the starting sector of a given cluster
@@ -56,9 +228,52 @@ impl VFat {
         self.data_start_sector + (cluster.get() as u64 - 2) * self.sectors_per_cluster as u64
     }
 
+    /// Returns the code page used to decode and encode 8.3 names.
+    pub fn code_page(&self) -> CodePage {
+        self.code_page
+    }
+
+    /// Sets the code page used to decode and encode 8.3 names. Defaults to
+    /// `CodePage::CP437`.
+    pub fn set_code_page(&mut self, code_page: CodePage) {
+        self.code_page = code_page;
+    }
+
+    /// Returns the offset, in seconds east of UTC, of the local time that
+    /// timestamps on the volume are stored in.
+    pub fn utc_offset(&self) -> i32 {
+        self.utc_offset
+    }
+
+    /// Sets the offset, in seconds east of UTC, of the local time that
+    /// timestamps on the volume are stored in. FAT doesn't record it, so it
+    /// defaults to 0 (UTC).
+    pub fn set_utc_offset(&mut self, utc_offset: i32) {
+        self.utc_offset = utc_offset;
+    }
+
+    /// Returns the current local time used to stamp new and modified entries,
+    /// or `None` if there is no clock to read it from.
+    #[cfg(not(target_os = "ros"))]
+    pub fn now(&self) -> Option<Timestamp> {
+        Some(Timestamp::from_system_time(SystemTime::now(), self.utc_offset))
+    }
+
+    /// Returns the current local time used to stamp new and modified entries,
+    /// or `None` if there is no clock to read it from.
+    #[cfg(target_os = "ros")]
+    pub fn now(&self) -> Option<Timestamp> {
+        None
+    }
+
+    /// Returns the first cluster of the root directory.
+    pub(crate) fn root_cluster(&self) -> Cluster {
+        self.root_dir_cluster
+    }
+
     // Calculate length of a cluster
     #[inline(always)]
-    fn bytes_per_cluster(&self) -> usize {
+    pub fn bytes_per_cluster(&self) -> usize {
         (self.bytes_per_sector as usize) * (self.sectors_per_cluster as usize)
     }
 
@@ -86,21 +301,264 @@ impl VFat {
         Ok(bytes_read)
     }
 
-    // A method to return a reference to a `FatEntry` for a cluster where the
-    // reference points directly into a cached sector.
-    pub fn fat_entry(&mut self, cluster: Cluster) -> io::Result<&FatEntry> {
-        // Calculate which sector the FAT entry of the cluster is in
-        let mut fat_offset = 4 * cluster.get() as usize;
+    /// Returns the range of sectors occupied by the file allocation tables.
+    /// Useful together with `PinMetadata` to keep the FAT cached.
+    pub fn fat_sectors(&self) -> Range<u64> {
+        self.fat_start_sector..self.data_start_sector
+    }
+
+    /// Bounds the sector cache to `capacity` sectors, evicting sectors
+    /// according to `policy` once it is full.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing back an evicted dirty sector fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn set_cache_policy<P>(&mut self, capacity: usize, policy: P) -> io::Result<()>
+        where P: CachePolicy + 'static
+    {
+        self.device.set_policy(capacity, policy)
+    }
+
+    /// Returns the sector cache counters collected since the file system was
+    /// mounted or since the last call to `reset_stats()`.
+    pub fn stats(&self) -> CacheStats {
+        self.device.stats()
+    }
+
+    /// Resets the sector cache counters, e.g. between workload phases.
+    pub fn reset_stats(&mut self) {
+        self.device.reset_stats()
+    }
+
+    /// Writes every pending change in the sector cache back to the
+    /// underlying device.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing a sector to the device fails.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.device.flush()
+    }
+
+    // Write `buf` into `cluster` starting at byte `offset` of the cluster
+    // Only the sectors touched by the write are modified
+    // Returns the number of bytes written, which is smaller than
+    // `buf.len()` when the write reaches the end of the cluster
+    fn _write_cluster(&mut self, cluster: Cluster, offset: usize, buf: &[u8]) -> io::Result<usize> {
+        let start_sector = self.cluster_to_sector(cluster);
+        let sector_size = self.bytes_per_sector as usize;
+        let len = min(buf.len(), self.bytes_per_cluster().saturating_sub(offset));
+
+        let mut bytes_written = 0;
+        while bytes_written < len {
+            let cur_offset = offset + bytes_written;
+            let sector = start_sector + (cur_offset / sector_size) as u64;
+            let sector_offset = cur_offset % sector_size;
+
+            // Write till the end of the sector or the end of the data
+            let cur_len = min(len - bytes_written, sector_size - sector_offset);
+            let data = self.device.get_mut(sector)?;
+            data[sector_offset..(sector_offset + cur_len)]
+                .copy_from_slice(&buf[bytes_written..(bytes_written + cur_len)]);
+            bytes_written += cur_len;
+        }
+        Ok(bytes_written)
+    }
+
+    // Return the cluster following `cluster` in its chain
+    // or `None` if `cluster` is the last one
+    fn next_cluster(&mut self, cluster: Cluster) -> io::Result<Option<Cluster>> {
+        match self.fat_entry(cluster)?.status() {
+            Status::Data(next) => Ok(Some(next)),
+            Status::Eoc(_) => Ok(None),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid FAT chain"))
+        }
+    }
+
+    /// Returns the number of free clusters in the file system.
+    ///
+    /// The count stored in FSInfo is used when it is known. Otherwise the FAT
+    /// is scanned once and the result is remembered (and stored in FSInfo).
+    pub fn free_clusters(&mut self) -> io::Result<u32> {
+        if let Some(free_count) = self.free_count {
+            return Ok(free_count);
+        }
+
+        let mut free_count = 0;
+        for raw in 2..(self.data_clusters + 2) {
+            if self.fat_entry(Cluster::from(raw))?.status() == Status::Free {
+                free_count += 1;
+            }
+        }
+        self.free_count = Some(free_count);
+        self.store_fsinfo()?;
+        Ok(free_count)
+    }
+
+    // Look for `count` free clusters, starting at cluster `start` and
+    // wrapping around at the end of the FAT.
+    // If `contiguous` is set, only a run of `count` consecutive free
+    // clusters is accepted. Returns `None` if not enough clusters were found.
+    fn find_free_clusters(&mut self, start: u32, count: usize, contiguous: bool) -> io::Result<Option<Vec<Cluster>>> {
+        let mut found = Vec::with_capacity(count);
+        for i in 0..self.data_clusters {
+            let raw = 2 + (start - 2 + i) % self.data_clusters;
+
+            // A run cannot wrap around the end of the FAT
+            if contiguous && raw == 2 {
+                found.clear();
+            }
+
+            let cluster = Cluster::from(raw);
+            if self.fat_entry(cluster)?.status() == Status::Free {
+                found.push(cluster);
+                if found.len() == count {
+                    return Ok(Some(found));
+                }
+            } else if contiguous {
+                found.clear();
+            }
+        }
+        Ok(None)
+    }
+
+    /// Allocates a free cluster and marks it as the end of its chain. If
+    /// `prev` is given, the new cluster is linked after `prev`.
+    ///
+    /// The content of the new cluster is left as is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Other` if there are no free clusters left.
+    pub(crate) fn alloc_cluster(&mut self, prev: Option<Cluster>) -> io::Result<Cluster> {
+        self.alloc_clusters(prev, 1)
+    }
+
+    /// Allocates a chain of `count` free clusters and returns its first
+    /// cluster. If `prev` is given, the chain is linked after `prev`.
+    ///
+    /// The search starts at the next-free hint from FSInfo. A contiguous run
+    /// of clusters is used when one exists, otherwise the chain is made of
+    /// the first `count` free clusters found. The free count and next-free
+    /// hint in FSInfo are updated accordingly.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Other` if there are fewer than `count` free
+    /// clusters left. Nothing is allocated in that case.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is 0.
+    pub(crate) fn alloc_clusters(&mut self, prev: Option<Cluster>, count: usize) -> io::Result<Cluster> {
+        assert!(count > 0);
+
+        if let Some(free_count) = self.free_count {
+            if (free_count as usize) < count {
+                return Err(io::Error::new(io::ErrorKind::Other, "No free cluster left"));
+            }
+        }
+
+        let start = self.next_free;
+        let clusters = match self.find_free_clusters(start, count, true)? {
+            Some(clusters) => clusters,
+            None => match self.find_free_clusters(start, count, false)? {
+                Some(clusters) => clusters,
+                None => return Err(io::Error::new(io::ErrorKind::Other, "No free cluster left"))
+            }
+        };
+
+        // Chain the new clusters together, then hook them up to `prev`
+        for pair in clusters.windows(2) {
+            self.set_fat_entry(pair[0], Status::Data(pair[1]))?;
+        }
+        let last = clusters[clusters.len() - 1];
+        self.set_fat_entry(last, Status::Eoc(0x0FFFFFFF))?;
+        if let Some(prev) = prev {
+            self.set_fat_entry(prev, Status::Data(clusters[0]))?;
+        }
+
+        self.next_free = 2 + (last.get() + 1 - 2) % self.data_clusters;
+        if let Some(free_count) = self.free_count {
+            self.free_count = Some(free_count - count as u32);
+        }
+        self.store_fsinfo()?;
+        Ok(clusters[0])
+    }
+
+    /// Marks every cluster in the chain starting at `start` as free, and
+    /// updates the free cluster count in FSInfo.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidData` if the chain is broken. The
+    /// clusters visited before the error are freed.
+    pub(crate) fn free_chain(&mut self, start: Cluster) -> io::Result<()> {
+        let mut cur = Some(start);
+        while let Some(cluster) = cur {
+            cur = self.next_cluster(cluster)?;
+            self.set_fat_entry(cluster, Status::Free)?;
+            if let Some(free_count) = self.free_count {
+                self.free_count = Some(free_count + 1);
+            }
+        }
+        self.store_fsinfo()
+    }
+
+    // Find the sector and the offset inside of it holding the entry
+    // of `cluster` in the FAT numbered `fat` (zero-based)
+    fn fat_entry_position(&self, fat: u8, cluster: Cluster) -> io::Result<(u64, usize)> {
+        let fat_offset = 4 * cluster.get() as usize;
         let sector_offset = fat_offset / (self.bytes_per_sector as usize);
-        fat_offset = fat_offset % (self.bytes_per_sector as usize);
         if sector_offset >= self.sectors_per_fat as usize {
             return Err(io::Error::new(io::ErrorKind::NotFound, "Out of boundary of FAT"));
         }
-        let data = self.device.get(self.fat_start_sector + sector_offset as u64)?;
+        let fat_start = self.fat_start_sector + fat as u64 * self.sectors_per_fat as u64;
+        Ok((fat_start + sector_offset as u64, fat_offset % (self.bytes_per_sector as usize)))
+    }
+
+    // A method to return a reference to a `FatEntry` for a cluster where the
+    // reference points directly into a cached sector.
+    // When FAT mirroring is disabled, the entry is read from the active FAT.
+    pub fn fat_entry(&mut self, cluster: Cluster) -> io::Result<&FatEntry> {
+        let fat = self.active_fat.unwrap_or(0);
+        let (sector, fat_offset) = self.fat_entry_position(fat, cluster)?;
+        let data = self.device.get(sector)?;
         return Ok(unsafe {
             &*(data[fat_offset..(fat_offset + 4)].as_ptr() as *const FatEntry)
         })
     }
+
+    /// Changes the FAT entry of `cluster` to `status`, keeping the reserved
+    /// high 4 bits of the entry intact.
+    ///
+    /// The new value is written into every FAT, unless FAT mirroring is
+    /// disabled in the BPB, in which case only the active FAT is updated.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `NotFound` if `cluster` is out of the bounds
+    /// of the FAT.
+    pub(crate) fn set_fat_entry(&mut self, cluster: Cluster, status: Status) -> io::Result<()> {
+        let fats = match self.active_fat {
+            Some(fat) => fat..(fat + 1),
+            None => 0..self.fat_num
+        };
+
+        for fat in fats {
+            let (sector, fat_offset) = self.fat_entry_position(fat, cluster)?;
+            let data = self.device.get_mut(sector)?;
+            let entry = unsafe {
+                &mut *(data[fat_offset..(fat_offset + 4)].as_mut_ptr() as *mut FatEntry)
+            };
+            entry.set_status(status);
+        }
+        Ok(())
+    }
 }
 
 pub trait VFatExt {
@@ -122,6 +580,17 @@ pub trait VFatExt {
         offset: usize,
         buf: &mut [u8]
     ) -> io::Result<usize>;
+
+    // A method to write a buffer into a cluster chain at an offset.
+    // Like `read_cluster`, `offset` can go over cluster boundaries.
+    // When the chain ends before all of `buf` is written, new clusters
+    // are allocated and appended to the chain.
+    fn write_cluster(
+        &self,
+        cluster: Cluster,
+        offset: usize,
+        buf: &[u8]
+    ) -> io::Result<usize>;
 }
 
 impl VFatExt for Shared<VFat> {
@@ -188,6 +657,71 @@ impl VFatExt for Shared<VFat> {
         }
         Ok(cur_buf_pos)
     }
+
+    fn write_cluster(
+        &self,
+        cluster: Cluster,
+        offset: usize,
+        buf: &[u8]
+    ) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let cluster_bytes = self.borrow().bytes_per_cluster();
+        let mut skip_clusters = offset / cluster_bytes;
+        let mut cur_offset = offset % cluster_bytes;
+        let mut cur_cluster = cluster;
+        let mut cur_buf_pos = 0;
+        loop {
+            if skip_clusters == 0 {
+                cur_buf_pos += self.borrow_mut()._write_cluster(cur_cluster, cur_offset, &buf[cur_buf_pos..])?;
+                cur_offset = 0;
+                if cur_buf_pos >= buf.len() {
+                    break;
+                }
+            } else {
+                skip_clusters -= 1;
+            }
+
+            // Move on to the next cluster, growing the chain if needed
+            let next = self.borrow_mut().next_cluster(cur_cluster)?;
+            cur_cluster = match next {
+                Some(next) => next,
+                None => self.borrow_mut().alloc_cluster(Some(cur_cluster))?
+            };
+        }
+        Ok(cur_buf_pos)
+    }
+}
+
+// Open the directory containing `path` and return it
+// along with the last component of `path`
+fn find_parent<'b>(vfat: &Shared<VFat>, path: &'b Path) -> io::Result<(Dir, &'b OsStr)> {
+    use traits::Entry;
+
+    if !path.has_root() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Can only start from root"));
+    }
+
+    let name = path.file_name()
+        .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "Path has no file name"))?;
+    let parent = path.parent()
+        .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "Path has no parent"))?;
+
+    match vfat.open(parent)?.into_dir() {
+        Some(dir) => Ok((dir, name)),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "Parent is not a directory"))
+    }
+}
+
+// Like `find_parent`, for creating an entry at `path`:
+// a missing parent is invalid input rather than a missing entry
+fn open_parent<'b>(vfat: &Shared<VFat>, path: &'b Path) -> io::Result<(Dir, &'b OsStr)> {
+    find_parent(vfat, path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => io::Error::new(io::ErrorKind::InvalidInput, "Parent directory does not exist"),
+        _ => e
+    })
 }
 
 impl<'a> FileSystem for &'a Shared<VFat> {
@@ -220,23 +754,69 @@ impl<'a> FileSystem for &'a Shared<VFat> {
         return Ok(cur_dir);
     }
 
-    fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::File> {
-        unimplemented!("read only file system")
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
+        let (parent, name) = open_parent(self, path.as_ref())?;
+        parent.create_file(name)
     }
 
-    fn create_dir<P>(self, _path: P, _parents: bool) -> io::Result<Self::Dir>
+    fn create_dir<P>(self, path: P, parents: bool) -> io::Result<Self::Dir>
         where P: AsRef<Path>
     {
-        unimplemented!("read only file system")
+        use traits::Entry;
+
+        let path = path.as_ref();
+        if !parents {
+            let (parent, name) = open_parent(self, path)?;
+            return parent.create_dir(name);
+        }
+
+        let name = path.file_name()
+            .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "Path has no file name"))?;
+        let parent = path.parent()
+            .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "Path has no parent"))?;
+
+        // Walk down to the parent, creating every missing directory
+        let mut cur_dir = Dir::from_root_cluster(self.clone(), self.borrow().root_dir_cluster);
+        let mut first = true;
+        for p in parent.components() {
+            if let Component::RootDir = p {
+                first = false;
+                continue;
+            }
+
+            if first {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Can only start from root"));
+            }
+
+            if let Component::Normal(component) = p {
+                let next = match cur_dir.find(component) {
+                    Ok(entry) => entry.into_dir()
+                        .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "Not a folder"))?,
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => cur_dir.create_dir(component)?,
+                    Err(e) => return Err(e)
+                };
+                cur_dir = next;
+            } else {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Can only start from root"));
+            }
+        }
+
+        if first {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Can only start from root"));
+        }
+        cur_dir.create_dir(name)
     }
 
-    fn rename<P, Q>(self, _from: P, _to: Q) -> io::Result<()>
+    fn rename<P, Q>(self, from: P, to: Q) -> io::Result<()>
         where P: AsRef<Path>, Q: AsRef<Path>
     {
-        unimplemented!("read only file system")
+        let (from_parent, from_name) = find_parent(self, from.as_ref())?;
+        let (to_parent, to_name) = open_parent(self, to.as_ref())?;
+        from_parent.rename(from_name, &to_parent, to_name)
     }
 
-    fn remove<P: AsRef<Path>>(self, _path: P, _children: bool) -> io::Result<()> {
-        unimplemented!("read only file system")
+    fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()> {
+        let (parent, name) = find_parent(self, path.as_ref())?;
+        parent.remove(name, children)
     }
 }